@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::{collections::HashMap, io::Read, path::Path};
+
+use crate::parsers::{open_input, CanId, ParserError};
+
+/// Derives CAN IDs which carry a device number in some of their bits from the ID without that number.
+///
+/// The name of derived IDs is made from `name_template`, replacing `{str_id}` with the name of the
+/// matched ID, `{device}` with the device number and `{device_name}` with the name of the device (or
+/// its number, if it has no name).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdRule {
+    /// Bits of the ID holding the device number
+    pub mask: u32,
+    /// Position of the lowest bit of the device number
+    pub shift: u32,
+    #[serde(rename = "name")]
+    pub name_template: String,
+    /// Names of the devices by their number
+    #[serde(default, deserialize_with = "device_names")]
+    pub devices: HashMap<u32, String>,
+}
+
+/// TOML keys are strings, so device numbers are parsed from them.
+fn device_names<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<u32, String>, D::Error> {
+    let names = HashMap::<String, String>::deserialize(deserializer)?;
+    names
+        .into_iter()
+        .map(|(device, name)| {
+            let device = match device.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => device.parse(),
+            };
+            device
+                .map(|device| (device, name))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+impl IdRule {
+    /// Bits 12 to 15 hold the device number, e.g. CAN_ID_PRESSURE-DEV3 for device 3.
+    pub fn legacy() -> Self {
+        Self {
+            mask: 0xF << 12,
+            shift: 12,
+            name_template: "{str_id}-DEV{device}".to_string(),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// The ID without the device number.
+    pub fn base_id(&self, hex_id: u32) -> u32 {
+        hex_id & !self.mask
+    }
+
+    pub fn device(&self, hex_id: u32) -> u32 {
+        (hex_id & self.mask) >> self.shift
+    }
+
+    /// Derives the CAN ID `hex_id` of a device from the ID without the device number. Nothing is
+    /// derived from IDs without a name, which are not in the CAN IDs file.
+    pub fn derive(&self, base: &CanId, hex_id: u32) -> Option<CanId> {
+        let str_id = base.str_id.to_owned()?;
+        let device = self.device(hex_id);
+        let device_name = self.devices.get(&device).cloned();
+        let name = self
+            .name_template
+            .replace("{str_id}", &str_id)
+            .replace("{device}", &device.to_string())
+            .replace(
+                "{device_name}",
+                device_name.as_deref().unwrap_or(&device.to_string()),
+            );
+        Some(CanId {
+            hex_id,
+            str_id: Some(name),
+            device: Some(device),
+            device_name,
+            derived_from: Some(str_id),
+            ..base.clone()
+        })
+    }
+}
+
+/// The rules deriving CAN IDs of devices, tried in order.
+///
+/// ```toml
+/// [[rules]]
+/// mask = 0xF000
+/// shift = 12
+/// name = "{str_id}-{device_name}"
+/// devices = { 1 = "Oxygenator", 2 = "Pump" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdRules {
+    #[serde(default)]
+    pub rules: Vec<IdRule>,
+}
+
+impl Default for IdRules {
+    /// The legacy rule only, see [`IdRule::legacy`].
+    fn default() -> Self {
+        Self {
+            rules: vec![IdRule::legacy()],
+        }
+    }
+}
+
+impl IdRules {
+    /// Loads the rules from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self, ParserError> {
+        let mut text = String::new();
+        open_input(path)?
+            .read_to_string(&mut text)
+            .map_err(|e| ParserError::io(path, e))?;
+        let bad_config = |message: String| ParserError::BadConfig {
+            path: path.as_ref().to_path_buf(),
+            message,
+        };
+        let rules: IdRules = toml::from_str(&text).map_err(|e| bad_config(e.to_string()))?;
+        for rule in &rules.rules {
+            if rule.mask == 0 || rule.shift >= 32 {
+                return Err(bad_config(format!(
+                    "mask 0x{:X} with shift {} does not select any bits",
+                    rule.mask, rule.shift
+                )));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Derives the CAN ID `hex_id` with the first rule matching a named ID of `can_ids`.
+    pub fn derive(&self, can_ids: &HashMap<u32, CanId>, hex_id: u32) -> Option<CanId> {
+        self.rules.iter().find_map(|rule| {
+            let base = can_ids.get(&rule.base_id(hex_id))?;
+            rule.derive(base, hex_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_only_from_named_ids() {
+        let rules = IdRules::default();
+        let named = CanId {
+            hex_id: 0x100,
+            str_id: Some("CAN_ID_PUMP".to_string()),
+            ..Default::default()
+        };
+        let can_ids = HashMap::from([(0x100, named), (0x200, CanId::empty_with_id(0x200))]);
+
+        let derived = rules.derive(&can_ids, 0x1100).unwrap();
+        assert_eq!(derived.str_id.as_deref(), Some("CAN_ID_PUMP-DEV1"));
+        assert_eq!(derived.derived_from.as_deref(), Some("CAN_ID_PUMP"));
+        assert!(rules.derive(&can_ids, 0x1200).is_none());
+        assert!(rules.derive(&can_ids, 0x1300).is_none());
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::parsers::{
+    parse_canids, parse_dbc, report_error, CanId, CanMsg, IdsFormat, LogIdInfo, ParserError,
+};
+
+mod id_rules;
+pub use id_rules::{IdRule, IdRules};
+
+/// All messages of a single CAN ID, along with the information about that ID.
+#[derive(Debug)]
+pub struct CanMsgCollection {
+    pub can_id: CanId,
+    pub collection: Vec<CanMsg>,
+}
+
+impl CanMsgCollection {
+    pub fn new(can_id: CanId, can_msg: CanMsg) -> Self {
+        Self {
+            can_id,
+            collection: vec![can_msg],
+        }
+    }
+}
+
+/// Parses the CAN IDs file and maps every CAN ID by its numerical value.
+///
+/// Unless `format` is given, it is guessed from the extension of the file.
+pub fn acquire_can_ids<P: AsRef<Path>>(
+    path: &P,
+    format: Option<IdsFormat>,
+    diagnostics: Option<&mut Vec<ParserError>>,
+) -> Result<HashMap<u32, CanId>, ParserError> {
+    let can_ids: Vec<CanId> = match format.unwrap_or_else(|| IdsFormat::from_path(path)) {
+        IdsFormat::Header => parse_canids(path, diagnostics)?,
+        IdsFormat::Dbc => parse_dbc(path, diagnostics)?,
+    };
+    let mut all_can_ids = HashMap::new();
+    for can_id in can_ids {
+        all_can_ids.insert(can_id.hex_id, can_id);
+    }
+    Ok(all_can_ids)
+}
+
+/// Adds CAN IDs for messages which carry device information in their ID, as described by `rules`.
+/// IDs which cannot be mapped at all are reported as [`ParserError::UnknownId`] and added to
+/// `unknown_ids`, so that checking further batches of messages reports them only once. They are not
+/// added to `can_ids`, so that no IDs are derived from them.
+pub fn check_can_ids(
+    can_msgs: &[CanMsg],
+    can_ids: &mut HashMap<u32, CanId>,
+    unknown_ids: &mut HashSet<u32>,
+    rules: &IdRules,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) {
+    let mut not_mappable_ids: HashSet<u32> = HashSet::new();
+
+    for msg in can_msgs.iter() {
+        if !can_ids.contains_key(&msg.hex_id) && !unknown_ids.contains(&msg.hex_id) {
+            // We still might be able to match: IDs can have device information
+            if let Some(new_can_id) = rules.derive(can_ids, msg.hex_id) {
+                // There is a match!
+                log::debug!("Created new CAN ID: {}", new_can_id);
+
+                can_ids.insert(msg.hex_id, new_can_id);
+            } else {
+                // The information is missing completely
+                let _ = not_mappable_ids.insert(msg.hex_id);
+            }
+        }
+    }
+
+    let mut not_mappable_ids: Vec<u32> = not_mappable_ids.into_iter().collect();
+    not_mappable_ids.sort();
+    for hex_id in not_mappable_ids {
+        report_error(ParserError::UnknownId { hex_id }, &mut diagnostics);
+        unknown_ids.insert(hex_id);
+    }
+}
+
+/// Decodes the values of messages which were logged as raw frames only (their value is NaN).
+/// Messages of IDs missing in `can_ids` keep their value, see [`check_can_ids`].
+pub fn decode_values(can_msgs: &mut [CanMsg], can_ids: &HashMap<u32, CanId>) {
+    for msg in can_msgs.iter_mut() {
+        if let (true, Some(payload), Some(can_id)) =
+            (msg.value.is_nan(), &msg.payload, can_ids.get(&msg.hex_id))
+        {
+            msg.value = can_id.decode(payload);
+        }
+    }
+}
+
+/// Timestamps and values of a signal, decoded from the raw frames of its CAN ID.
+#[derive(Debug, Default)]
+pub struct SignalValues {
+    pub ts: Vec<u64>,
+    pub values: Vec<f64>,
+}
+
+/// Decodes the signals of `can_id` from the raw frames of messages of that ID, in the order of its
+/// signals. Multiplexed signals only get values from the frames selecting them.
+pub fn decode_signals(can_id: &CanId, can_msgs: &[CanMsg]) -> Vec<SignalValues> {
+    let mut decoded: Vec<SignalValues> = can_id
+        .signals
+        .iter()
+        .map(|_| SignalValues::default())
+        .collect();
+    if decoded.is_empty() {
+        return decoded;
+    }
+    for msg in can_msgs {
+        let Some(payload) = &msg.payload else {
+            continue;
+        };
+        for (signal, value) in decoded
+            .iter_mut()
+            .zip(can_id.decode_signals(payload.bytes()))
+        {
+            if let Some(value) = value {
+                signal.ts.push(msg.ts);
+                signal.values.push(value);
+            }
+        }
+    }
+    decoded
+}
+
+/// Compares unit and description given in logs to the ones of the CAN IDs file.
+///
+/// Differences are reported as [`ParserError::MetadataMismatch`]. If `fill_missing` is set, units and
+/// descriptions missing in the CAN IDs file are taken from the logs. IDs not in `can_ids` are skipped.
+pub fn cross_check_ids(
+    id_infos: &[(u32, LogIdInfo)],
+    can_ids: &mut HashMap<u32, CanId>,
+    fill_missing: bool,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) {
+    for (hex_id, info) in id_infos {
+        let Some(can_id) = can_ids.get_mut(hex_id) else {
+            continue;
+        };
+        for (field, in_log, in_header) in [
+            ("unit", &info.unit, &mut can_id.unit),
+            ("description", &info.description, &mut can_id.description),
+        ] {
+            match (in_log, in_header) {
+                (Some(in_log), Some(in_header)) if !same_metadata(in_log, in_header) => {
+                    report_error(
+                        ParserError::MetadataMismatch {
+                            hex_id: *hex_id,
+                            field,
+                            in_log: in_log.to_owned(),
+                            in_header: in_header.to_owned(),
+                            path: info.path.clone(),
+                            line_nr: info.line_nr,
+                        },
+                        &mut diagnostics,
+                    );
+                }
+                (Some(in_log), in_header @ None) if fill_missing => {
+                    log::debug!("Taking {field} {in_log:?} of 0x{hex_id:X} from the logs");
+                    *in_header = Some(in_log.to_owned());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Compares ignoring case and differences in whitespace.
+fn same_metadata(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Groups messages by their CAN ID. `can_msgs` has to be sorted by CAN ID.
+pub fn create_collection(
+    can_msgs: &[CanMsg],
+    can_ids: &HashMap<u32, CanId>,
+) -> Vec<CanMsgCollection> {
+    let mut collection: Vec<CanMsgCollection> = Vec::new();
+    let mut last_id: u32 = 0;
+    for msg in can_msgs.iter() {
+        if msg.hex_id > last_id {
+            // This is a new CAN ID. We thus have to create a new collection first.
+            last_id = msg.hex_id;
+
+            // Construct the CAN ID to be inserted into the collection.
+            let can_id = match can_ids.get(&msg.hex_id) {
+                None => CanId::empty_with_id(msg.hex_id),
+                Some(id) => id.to_owned(),
+            };
+            log::trace!("{}", can_id);
+            collection.push(CanMsgCollection::new(can_id, *msg));
+        } else {
+            collection.last_mut().unwrap().collection.push(*msg);
+        }
+    }
+
+    collection
+}
+
+/// Smallest number of trailing zeros over all timestamps, capped at 9 (nanoseconds).
+pub fn least_trailing_zeros(can_msgs: &[CanMsg]) -> u32 {
+    let mut trailing_zeros = 9;
+    for can_msg in can_msgs {
+        let msg_trailing = can_msg.ts.trailing_zeros();
+        if msg_trailing < trailing_zeros {
+            trailing_zeros = msg_trailing;
+        }
+    }
+    trailing_zeros
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(hex_id: u32) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unknown_ids_are_reported_once_and_not_derived_from() {
+        let mut can_ids = HashMap::new();
+        let mut unknown_ids = HashSet::new();
+        let mut diagnostics = Vec::new();
+        let rules = IdRules::default();
+
+        check_can_ids(
+            &[msg(0x100), msg(0x100)],
+            &mut can_ids,
+            &mut unknown_ids,
+            &rules,
+            Some(&mut diagnostics),
+        );
+        check_can_ids(
+            &[msg(0x100), msg(0x1100)],
+            &mut can_ids,
+            &mut unknown_ids,
+            &rules,
+            Some(&mut diagnostics),
+        );
+
+        assert!(can_ids.is_empty());
+        assert_eq!(unknown_ids, HashSet::from([0x100, 0x1100]));
+        let reported: Vec<u32> = diagnostics
+            .iter()
+            .map(|e| match e {
+                ParserError::UnknownId { hex_id } => *hex_id,
+                e => panic!("unexpected {e}"),
+            })
+            .collect();
+        assert_eq!(reported, [0x100, 0x1100]);
+    }
+
+    #[test]
+    fn device_ids_are_derived_from_known_ids() {
+        let base = CanId {
+            hex_id: 0x100,
+            str_id: Some("CAN_ID_PUMP".to_string()),
+            ..Default::default()
+        };
+        let mut can_ids = HashMap::from([(0x100, base)]);
+        let mut unknown_ids = HashSet::new();
+
+        check_can_ids(
+            &[msg(0x3100)],
+            &mut can_ids,
+            &mut unknown_ids,
+            &IdRules::default(),
+            None,
+        );
+
+        assert!(unknown_ids.is_empty());
+        let derived = &can_ids[&0x3100];
+        assert_eq!(derived.str_id.as_deref(), Some("CAN_ID_PUMP-DEV3"));
+        assert_eq!(derived.device, Some(3));
+    }
+
+    fn info(unit: Option<&str>, description: Option<&str>) -> LogIdInfo {
+        LogIdInfo {
+            unit: unit.map(str::to_string),
+            description: description.map(str::to_string),
+            path: "ext.log".into(),
+            line_nr: 7,
+        }
+    }
+
+    fn pressure() -> HashMap<u32, CanId> {
+        let can_id = CanId {
+            hex_id: 0x100,
+            unit: Some("mmHg".to_string()),
+            description: Some("Blood  pressure".to_string()),
+            ..Default::default()
+        };
+        HashMap::from([(0x100, can_id)])
+    }
+
+    #[test]
+    fn mismatching_units_are_reported() {
+        let mut can_ids = pressure();
+        let mut diagnostics = Vec::new();
+        let infos = [(0x100, info(Some("bar"), Some("blood pressure")))];
+        cross_check_ids(&infos, &mut can_ids, true, Some(&mut diagnostics));
+
+        match &diagnostics[..] {
+            [ParserError::MetadataMismatch {
+                hex_id: 0x100,
+                field: "unit",
+                in_log,
+                in_header,
+                line_nr: 7,
+                ..
+            }] => assert_eq!((in_log.as_str(), in_header.as_str()), ("bar", "mmHg")),
+            diagnostics => panic!("{diagnostics:?}"),
+        }
+        assert_eq!(can_ids[&0x100].unit.as_deref(), Some("mmHg"));
+    }
+
+    #[test]
+    fn mismatching_descriptions_are_reported() {
+        let mut can_ids = pressure();
+        let mut diagnostics = Vec::new();
+        let infos = [(0x100, info(Some("MMHG"), Some("Flow")))];
+        cross_check_ids(&infos, &mut can_ids, false, Some(&mut diagnostics));
+
+        assert!(matches!(
+            &diagnostics[..],
+            [ParserError::MetadataMismatch {
+                field: "description",
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn missing_metadata_is_filled_from_the_logs_only_if_asked() {
+        let bare = HashMap::from([(0x100, CanId::empty_with_id(0x100))]);
+        let infos = [
+            (0x100, info(Some("L/min"), Some("Blood flow"))),
+            (0x200, info(Some("%"), None)),
+        ];
+        let mut diagnostics = Vec::new();
+
+        let mut can_ids = bare.clone();
+        cross_check_ids(&infos, &mut can_ids, false, Some(&mut diagnostics));
+        assert_eq!(can_ids[&0x100].unit, None);
+
+        cross_check_ids(&infos, &mut can_ids, true, Some(&mut diagnostics));
+        assert_eq!(can_ids[&0x100].unit.as_deref(), Some("L/min"));
+        assert_eq!(can_ids[&0x100].description.as_deref(), Some("Blood flow"));
+        assert!(!can_ids.contains_key(&0x200));
+        assert!(diagnostics.is_empty());
+    }
+}
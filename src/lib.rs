@@ -0,0 +1,25 @@
+//! Parses legacy CAN logs into HDF5, using a SmartECLA_IDs.h file (or similar) to map
+//! the CAN IDs found in the logs to names, units, scales and descriptions.
+//!
+//! The `can-parser` binary is a thin client of this library. Other tools may use the
+//! parsers, the collection stage and the writers (HDF5, Parquet, Arrow IPC, SQLite, CSV or MDF4) directly.
+
+pub mod collection;
+pub mod parsers;
+pub mod writers;
+
+pub use collection::{
+    acquire_can_ids, check_can_ids, create_collection, cross_check_ids, decode_signals,
+    decode_values, least_trailing_zeros, CanMsgCollection, IdRule, IdRules, SignalValues,
+};
+pub use parsers::{
+    date_from_file_name, is_stdin, open_input, parse_blf_objects, parse_blocks, parse_canids,
+    parse_comments, parse_comments_date, parse_dbc, parse_id_info, parse_messages, AscHeader,
+    BlfHeader, ByteOrder, CanCmt, CanId, CanLogReader, CanMsg, CanPayload, CanSignal, IdsFormat,
+    InputReader, LogBlock, LogBlockReader, LogFormat, LogIdInfo, LogLine, LogTime, Multiplexing,
+    ParsedBlock, ParserError, RecordingTimeZone, TimestampContext, TrcHeader, TrcLayout, ValueType,
+};
+pub use writers::{
+    is_stdout, write_to_hdf5, Alignment, ArrowIpcWriter, CanMeta, CanWriter, CsvWriter, Hdf5Writer,
+    Mdf4Writer, OutputFormat, ParquetWriter, SqliteWriter, WriterError, STDOUT_PATH,
+};
@@ -1,238 +1,183 @@
+use chrono::NaiveDate;
 use clap::Parser;
-use hdf5::types::VarLenUnicode;
-use hdf5::Location;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     path::{Path, PathBuf},
+    process::exit,
     time::SystemTime,
 };
 
-mod parsers;
-use parsers::{parse_canids, parse_comments, parse_messages, CanCmt, CanId, CanMsg};
+use can_parser::{
+    acquire_can_ids, check_can_ids, cross_check_ids, date_from_file_name, decode_values, is_stdin,
+    is_stdout, least_trailing_zeros, parse_blocks, parse_comments, parse_comments_date, Alignment,
+    ArrowIpcWriter, CanCmt, CanMeta, CanMsg, CanWriter, CsvWriter, Hdf5Writer, IdRules, IdsFormat,
+    LogBlock, LogBlockReader, LogFormat, LogIdInfo, Mdf4Writer, OutputFormat, ParquetWriter,
+    ParserError, RecordingTimeZone, SqliteWriter, TimestampContext, WriterError,
+};
 use pretty_env_logger::env_logger::{Builder, Env};
 
-#[derive(Debug)]
-struct CanMsgCollection {
-    can_id: CanId,
-    collection: Vec<CanMsg>,
-}
-
-impl CanMsgCollection {
-    fn new(can_id: CanId, can_msg: CanMsg) -> Self {
-        Self {
-            can_id,
-            collection: vec![can_msg],
-        }
-    }
-}
-
 #[derive(Parser)]
 #[command(author, version)]
 #[command(
-    about = "Uses a SmartECLA_IDs.h file (or similar) to parse a legacy CAN log to HDF5.
+    about = "Uses a SmartECLA_IDs.h file (or similar) to parse a legacy CAN log to HDF5, Parquet, Arrow IPC, SQLite, CSV or MDF4.
+Besides legacy logs (simple or extended), Vector ASC and BLF, candump and PCAN trace (.trc) logs are read.
+The format is detected per file.
+Inputs compressed with gzip, zstd or xz are decompressed on the fly, '-' reads an input from stdin.
+Values of logs with raw frames only are decoded with the ID's only signal (from a DBC file),
+or else as little endian i32 of the first four bytes times scale.
+Signals of IDs from DBC files are decoded from raw frames into 'CAN_SIGNALS/<ID>/<signal>' as ts (u64) and value (f64),
+with attributes hex_id, unit, scale, offset, min, max, description and, if multiplexed, multiplexer_value.
 In the HDF5 file, every CAN ID is stored as a dataset in the 'CAN_IDs' group.
+With --group-by-device, the datasets are stored in 'devices/<device>/<prefix>/<name>' instead, where <device>
+is the device name or number from the ID rules (or 'common') and <prefix> the name up to its last '_'.
+'CAN_IDs' then holds soft links to them.
 Every dataset has four attributes: hex_id (u32), unit (String), scale (f32) and description (String).
+Datasets of IDs derived by an ID rule also have device (u32) and, if named, device_name (String).
 Every entry in every dataset is stored along with the time it was acquired.
-When a comments file is supplied, comments will be stored in /COMMENTS."
+When a comments file is supplied, comments will be stored in /COMMENTS.
+With --raw, the frames of extended logs are stored in 'CAN_RAW' as ts (u64), dlc (u8) and data ([u8; 8]).
+If the recording date is known (from --start-date, the logs, their file names or the comments),
+timestamps are nanoseconds since the Unix epoch, otherwise since midnight of the first day.
+Parquet output is a long table of hex_id (u32), str_id (String), ts (u64) and value (f32), with unit, scale
+and description of every ID in the key-value metadata as '<ID>.unit' etc. With --file-per-id, the output path
+is a directory with a '<ID>.parquet' file of ts and value per ID instead. Comments are stored in
+'<output>.comments.parquet' (or 'COMMENTS.parquet' in the directory) as id (u32), ts (u64) and comment (String).
+Arrow IPC (Feather V2) output has the same layout as Parquet, with '.arrow' files and the metadata of the IDs
+in the schema metadata, or with --file-per-id as field metadata of the value. With '-' as output path,
+the long table is streamed to stdout in the IPC stream format, without comments.
+SQLite output has the tables can_ids (hex_id, str_id, description, unit, scale, device, device_name,
+derived_from), messages (hex_id, ts, value) indexed by ID and time, comments (id, ts, comment) and meta (key, value).
+CSV output is a long table of hex_id, str_id, time (in seconds) and value. With --wide, it is a table with
+a column per ID, named by str_id, and a second header row with the units instead. Its rows are at every
+time a message was received or every --time-step seconds, with the values aligned by --align: 'hold'
+repeats the last value, 'interpolate' interpolates linearly. With --decimal-separator ',', fields are
+separated by ';'. Comments are stored in '<output>.comments.csv' as id, time and comment.
+MDF4 output has a channel group per ID, named by str_id, with a master channel 'time' in seconds since the
+first message and a value channel with the unit, description and a linear conversion by the scale.
+Comments are stored as marker events."
 )]
 struct CanHdfCli {
-    /// Path, where the resulting HDF file should be written to
+    /// Path, where the resulting HDF (or Parquet, Arrow IPC, SQLite, CSV, MDF4) file should be written to, '-' streams Arrow IPC (or CSV) to stdout
     output_path: PathBuf,
 
-    /// Path to SmartECLA_IDs.h (or similar)
+    /// Path to SmartECLA_IDs.h (or similar) or a DBC file
     can_ids_path: PathBuf,
 
     /// Path to all CAN log files of the experiment.
     #[arg(required = true)]
     can_log_paths: Vec<PathBuf>,
 
-    /// Indicate extended CAN logs (with hex data representations)
+    /// Parse all logs as extended CAN logs (with hex data representations) instead of detecting the format per file
     #[arg(short)]
     extended_log: bool,
 
+    /// Format of the CAN IDs file: "header" or "dbc", guessed from its extension by default
+    #[arg(long)]
+    ids_format: Option<IdsFormat>,
+
+    /// TOML file with rules deriving the CAN IDs of devices, by default bits 12 to 15 are the device number
+    #[arg(long)]
+    id_rules: Option<PathBuf>,
+
     /// Also parse comments file
     #[arg(short)]
     comments_path: Option<PathBuf>,
-}
 
-struct CanMeta<'a> {
-    cli: &'a CanHdfCli,
-    time_ms: u128,
-    old_size_b: u64,
-    least_trailing_zeros: u32,
-}
+    /// Fail if any line could not be parsed or any CAN ID could not be mapped
+    #[arg(long)]
+    strict: bool,
 
-fn create_str_attr(location: &Location, name: &str, value: &str) -> hdf5::Result<()> {
-    let attr = location.new_attr::<VarLenUnicode>().create(name)?;
-    let value_: VarLenUnicode = value.parse().unwrap();
-    attr.write_scalar(&value_)
-}
+    /// Number of messages parsed before they are written to the HDF file
+    #[arg(long, default_value_t = 1_000_000)]
+    batch_size: usize,
 
-fn write_to_hdf5<'a, P: AsRef<Path>>(
-    output_path: &P,
-    collections: &Vec<CanMsgCollection>,
-    can_cmts: &Vec<CanCmt>,
-    meta: &'a CanMeta,
-) -> hdf5::Result<()> {
-    let root = hdf5::File::create(output_path)?;
-    create_str_attr(&root, "created", chrono::Local::now().to_rfc3339().as_str())?;
-    create_str_attr(
-        &root,
-        "CAN IDs file",
-        meta.cli.can_ids_path.as_os_str().to_str().unwrap(),
-    )?;
-    root.new_attr::<usize>()
-        .create("Processing time")?
-        .write_scalar(&(meta.time_ms as usize))?;
-    root.new_attr::<usize>()
-        .create("Dataset count")?
-        .write_scalar(&collections.len())?;
-    root.new_attr::<u64>()
-        .create("Previous size [B]")?
-        .write_scalar(&meta.old_size_b)?;
-    root.new_attr::<u32>()
-        .create("TS least trailing zeros")?
-        .write_scalar(&meta.least_trailing_zeros)?;
-
-    let ds_group = root.create_group("CAN_IDs")?;
-
-    for collection in collections {
-        let str_id = match &collection.can_id.str_id {
-            Some(str_id) => str_id.to_owned(),
-            None => collection.can_id.hex_id.to_string(),
-        };
-
-        let dataset = ds_group
-            .new_dataset_builder()
-            .with_data(&collection.collection)
-            .set_filters(&[hdf5::filters::Filter::Deflate(5)])
-            .create(str_id.as_str())?;
-
-        dataset
-            .new_attr::<u32>()
-            .create("hex_id")?
-            .write_scalar(&collection.can_id.hex_id)?;
-
-        let desc = match &collection.can_id.description {
-            Some(desc) => desc.as_str(),
-            None => "None",
-        };
-        create_str_attr(&dataset, "description", desc)?;
-
-        let unit = match &collection.can_id.unit {
-            Some(unit) => unit.as_str(),
-            None => "None",
-        };
-        create_str_attr(&dataset, "unit", unit)?;
-
-        let scale = match &collection.can_id.scale {
-            Some(scale) => scale,
-            None => &1.0,
-        };
-        dataset
-            .new_attr::<f32>()
-            .create("scale")?
-            .write_scalar(scale)?;
-
-        log::debug!("Written dataset {}", str_id);
-    }
+    /// Date the recording started (YYYY-MM-DD), overriding dates found in file names and comments
+    #[arg(long)]
+    start_date: Option<NaiveDate>,
 
-    if !can_cmts.is_empty() {
-        root.new_dataset_builder()
-            .with_data(&can_cmts)
-            .set_filters(&[hdf5::filters::Filter::Deflate(5)])
-            .create("COMMENTS")?;
-
-        create_str_attr(
-            &root,
-            "Comments file path",
-            meta.cli
-                .comments_path
-                .as_ref()
-                .unwrap()
-                .as_os_str()
-                .to_str()
-                .unwrap(),
-        )?;
-
-        for logfile in &meta.cli.can_log_paths {
-            create_str_attr(&root, "Log file ", logfile.as_os_str().to_str().unwrap())?;
-        }
-    }
+    /// Time zone of the logged clock times: "local", "UTC" or an offset like "+01:00"
+    #[arg(long, default_value = "local")]
+    timezone: RecordingTimeZone,
+
+    /// Take units and descriptions missing in the CAN IDs file from extended logs
+    #[arg(long)]
+    fill_metadata: bool,
 
-    log::debug!("Wrote comments to COMMENTS");
+    /// Also store DLC and data bytes of extended logs in the 'CAN_RAW' group
+    #[arg(long)]
+    raw: bool,
 
-    Ok(())
+    /// Store datasets in 'devices/<device>/<prefix>/<name>', with soft links to them in 'CAN_IDs'
+    #[arg(long)]
+    group_by_device: bool,
+
+    /// Output format: "hdf5", "parquet", "arrow", "sqlite", "csv" or "mdf4", guessed from the output path's extension by default
+    #[arg(long)]
+    output_format: Option<OutputFormat>,
+
+    /// Write a Parquet or Arrow IPC file per CAN ID into the output directory instead of a single long table
+    #[arg(long)]
+    file_per_id: bool,
+
+    /// Write a CSV table with a column per CAN ID instead of a long table
+    #[arg(long)]
+    wide: bool,
+
+    /// Alignment of the values to the rows of a wide CSV table: "hold" or "interpolate"
+    #[arg(long, default_value = "hold")]
+    align: Alignment,
+
+    /// Rows of a wide CSV table every this many seconds, instead of at every time a message was received
+    #[arg(long)]
+    time_step: Option<f64>,
+
+    /// Decimal separator of CSV tables, with ',' fields are separated by ';'
+    #[arg(long, default_value_t = '.')]
+    decimal_separator: char,
+
+    /// Number of threads used for parsing, 0 uses one per CPU core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
 }
 
-fn acquire_can_ids<P: AsRef<Path>>(path: &P) -> HashMap<u32, CanId> {
-    let can_ids: Vec<CanId> = parse_canids(path);
-    let mut all_can_ids = HashMap::new();
-    for can_id in can_ids {
-        all_can_ids.insert(can_id.hex_id, can_id);
-    }
-    all_can_ids
+/// Log files are split into blocks of about this size, which are parsed in parallel.
+const BLOCK_SIZE_B: usize = 4 << 20;
+
+fn or_exit<T>(result: Result<T, ParserError>) -> T {
+    result.unwrap_or_else(|e| {
+        log::error!("{}", e.render());
+        exit(1)
+    })
 }
 
-fn check_can_ids(can_msgs: &Vec<CanMsg>, can_ids: &mut HashMap<u32, CanId>) {
-    let mut not_mappable_ids: HashSet<u32> = HashSet::new();
-
-    for msg in can_msgs.iter() {
-        if let None = can_ids.get(&msg.hex_id) {
-            // We still might be able to match: IDs can have device information
-            if let Some(id) = can_ids.get(&(msg.hex_id & !(0xF << 12))) {
-                // There is a match!
-                log::debug!("Found {} for unmatched {}", id, msg.hex_id);
-                let mut new_str_id = id.str_id.to_owned().unwrap();
-                new_str_id.push_str(format!("-DEV{}", (msg.hex_id >> 12) & 0xF).as_str());
-
-                let new_can_id = CanId {
-                    hex_id: msg.hex_id,
-                    str_id: Some(new_str_id),
-                    scale: id.scale,
-                    description: id.description.clone(),
-                    unit: id.unit.clone(),
-                };
-
-                log::debug!("\tCreated new CAN ID: {}", new_can_id);
-
-                can_ids.insert(msg.hex_id, new_can_id);
-            } else {
-                // The information is missing completely
-                let _ = not_mappable_ids.insert(msg.hex_id);
-            }
-        }
+/// Logs and clears the collected diagnostics, returning how many there were.
+fn report_diagnostics(diagnostics: &mut Vec<ParserError>) -> usize {
+    for diagnostic in diagnostics.iter() {
+        log::warn!("{}", diagnostic.render());
     }
+    let count = diagnostics.len();
+    diagnostics.clear();
+    count
+}
 
-    for nm in not_mappable_ids {
-        log::warn!("Not mappable: {}", nm);
-    }
+fn write_or_exit<T>(result: Result<T, WriterError>, output_path: &Path) -> T {
+    result.unwrap_or_else(|e| {
+        log::error!("Could not write {:#?}: {e}", output_path.as_os_str());
+        exit(1)
+    })
 }
 
-fn create_collection(
-    can_msgs: &Vec<CanMsg>,
-    can_ids: &HashMap<u32, CanId>,
-) -> Vec<CanMsgCollection> {
-    let mut collection: Vec<CanMsgCollection> = Vec::new();
-    let mut last_id: u32 = 0;
-    for msg in can_msgs.iter() {
-        if msg.hex_id > last_id {
-            // This is a new CAN ID. We thus have to create a new collection first.
-            last_id = msg.hex_id;
-
-            // Construct the CAN ID to be inserted into the collection.
-            let can_id = match can_ids.get(&msg.hex_id) {
-                None => CanId::empty_with_id(msg.hex_id),
-                Some(id) => id.to_owned(),
-            };
-            log::trace!("{}", can_id);
-            collection.push(CanMsgCollection::new(can_id, *msg));
-        } else {
-            collection.last_mut().unwrap().collection.push(*msg);
-        }
+/// Size of the output file, or of all files in the output directory.
+fn output_size_b(output_path: &Path) -> u64 {
+    match std::fs::read_dir(output_path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum(),
+        Err(_) => std::fs::metadata(output_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0),
     }
-
-    collection
 }
 
 fn main() {
@@ -243,70 +188,308 @@ fn main() {
     let start = SystemTime::now();
 
     let cli_input = CanHdfCli::parse();
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli_input.threads)
+        .build_global()
+    {
+        log::error!("Could not start the parser threads: {e}");
+        exit(1);
+    }
+    let output_path = cli_input.output_path.as_path();
+    let stdin_inputs = std::iter::once(&cli_input.can_ids_path)
+        .chain(&cli_input.id_rules)
+        .chain(&cli_input.comments_path)
+        .chain(&cli_input.can_log_paths)
+        .filter(is_stdin)
+        .count();
+    if stdin_inputs > 1 {
+        log::error!("Only one input can be read from stdin");
+        exit(1);
+    }
 
     log::info!(
         "Collecting CAN IDs from {:#?}",
         cli_input.can_ids_path.as_os_str()
     );
-    let mut can_ids = acquire_can_ids(&cli_input.can_ids_path);
-
-    let mut total_size_b = 0;
-    let mut can_msgs: Vec<CanMsg> = Vec::new();
-    for (i, log_path) in cli_input.can_log_paths.iter().enumerate() {
-        log::info!(
-            "Parsing log file {:#?} ({}/{})...",
-            log_path.as_os_str(),
-            i + 1,
-            cli_input.can_log_paths.len()
-        );
-        can_msgs.append(&mut parse_messages(&log_path, cli_input.extended_log));
-        total_size_b += std::fs::metadata(&log_path).unwrap().len();
+    let mut diagnostics: Vec<ParserError> = Vec::new();
+    let mut can_ids = or_exit(acquire_can_ids(
+        &cli_input.can_ids_path,
+        cli_input.ids_format,
+        Some(&mut diagnostics),
+    ));
+    let mut diagnostic_count = report_diagnostics(&mut diagnostics);
+    let id_rules = match &cli_input.id_rules {
+        Some(path) => or_exit(IdRules::load(path)),
+        None => IdRules::default(),
+    };
+
+    let output_format = cli_input
+        .output_format
+        .unwrap_or_else(|| OutputFormat::from_path(&output_path));
+    let streamable = [OutputFormat::ArrowIpc, OutputFormat::Csv];
+    if is_stdout(&output_path) && (!streamable.contains(&output_format) || cli_input.file_per_id) {
+        log::error!("Only a single Arrow IPC or CSV table can be streamed to stdout");
+        exit(1);
+    }
+    log::info!(
+        "Writing {output_format} to {:#?}...",
+        output_path.as_os_str()
+    );
+    let mut writer: Box<dyn CanWriter> = match output_format {
+        OutputFormat::Hdf5 => Box::new(
+            write_or_exit(
+                Hdf5Writer::create(&output_path).map_err(WriterError::from),
+                output_path,
+            )
+            .with_raw_payloads(cli_input.raw)
+            .with_device_groups(cli_input.group_by_device),
+        ),
+        OutputFormat::Parquet => {
+            Box::new(ParquetWriter::new(&output_path).with_file_per_id(cli_input.file_per_id))
+        }
+        OutputFormat::ArrowIpc => {
+            Box::new(ArrowIpcWriter::new(&output_path).with_file_per_id(cli_input.file_per_id))
+        }
+        OutputFormat::Sqlite => Box::new(write_or_exit(
+            SqliteWriter::create(&output_path),
+            output_path,
+        )),
+        OutputFormat::Csv => Box::new(
+            write_or_exit(CsvWriter::create(&output_path), output_path)
+                .with_decimal_separator(cli_input.decimal_separator)
+                .with_wide_table(cli_input.wide)
+                .with_alignment(cli_input.align)
+                .with_time_step(cli_input.time_step.map(|secs| (secs * 1e9) as u64)),
+        ),
+        OutputFormat::Mdf4 => {
+            Box::new(write_or_exit(Mdf4Writer::create(&output_path), output_path))
+        }
+    };
+    if output_format != OutputFormat::Hdf5 && (cli_input.raw || cli_input.group_by_device) {
+        log::warn!("--raw and --group-by-device only apply to HDF5 output");
+    }
+    let tables = [OutputFormat::Parquet, OutputFormat::ArrowIpc];
+    if cli_input.file_per_id && !tables.contains(&output_format) {
+        log::warn!("--file-per-id only applies to Parquet and Arrow IPC output");
+    }
+    if output_format != OutputFormat::Csv && (cli_input.wide || cli_input.time_step.is_some()) {
+        log::warn!("--wide and --time-step only apply to CSV output");
     }
 
+    let forced_format = cli_input.extended_log.then_some(LogFormat::Extended);
+    // Inputs from stdin can only be read once, so the first log is opened only once.
+    let mut first_log = cli_input
+        .can_log_paths
+        .first()
+        .map(|log_path| or_exit(LogBlockReader::open(log_path, forced_format, BLOCK_SIZE_B)));
+    let reference_date = cli_input
+        .start_date
+        .or_else(|| {
+            let first_log_path = cli_input.can_log_paths.first()?;
+            first_log
+                .as_ref()?
+                .format()
+                .start_date()
+                .or_else(|| date_from_file_name(first_log_path))
+        })
+        .or_else(|| {
+            let comments_path = cli_input.comments_path.as_ref()?;
+            if is_stdin(comments_path) {
+                return None;
+            }
+            or_exit(parse_comments_date(comments_path))
+        });
+    let start_context = match reference_date {
+        Some(date) => {
+            log::info!(
+                "Timestamps relative to {date} in time zone {}",
+                cli_input.timezone
+            );
+            TimestampContext::starting_at(date).in_time_zone(cli_input.timezone)
+        }
+        None => {
+            log::warn!("Recording date unknown, timestamps count from midnight of the first day");
+            TimestampContext::new()
+        }
+    };
+
     let mut trailing_zeros = 9;
-    for can_msg in &can_msgs {
-        let msg_trailing = can_msg.ts.trailing_zeros();
-        if msg_trailing < trailing_zeros {
-            trailing_zeros = msg_trailing;
+    let mut unknown_ids: HashSet<u32> = HashSet::new();
+    let mut batch: Vec<CanMsg> = Vec::with_capacity(cli_input.batch_size);
+    let mut context = start_context.clone();
+    let mut log_formats: Vec<Option<LogFormat>> = vec![None; cli_input.can_log_paths.len()];
+    // Units and descriptions from the logs are checked before the first messages of an ID are written.
+    let mut checked_ids: HashSet<u32> = HashSet::new();
+    let mut pending_id_infos: Vec<(u32, LogIdInfo)> = Vec::new();
+
+    // Blocks of all files are parsed in parallel, round by round. Resolving the timestamps and
+    // collecting the messages happens in order, so the output does not depend on the thread count.
+    let blocks_per_round = 2 * rayon::current_num_threads();
+    let mut log_blocks =
+        cli_input
+            .can_log_paths
+            .iter()
+            .enumerate()
+            .flat_map(|(file_nr, log_path)| {
+                first_log
+                    .take()
+                    .unwrap_or_else(|| {
+                        or_exit(LogBlockReader::open(log_path, forced_format, BLOCK_SIZE_B))
+                    })
+                    .map(move |block| (file_nr, or_exit(block)))
+            });
+    let mut current_file = None;
+    loop {
+        let (file_nrs, blocks): (Vec<usize>, Vec<LogBlock>) =
+            log_blocks.by_ref().take(blocks_per_round).unzip();
+        if blocks.is_empty() {
+            break;
+        }
+        let parsed = parse_blocks(&blocks);
+        for ((file_nr, block), parsed_block) in file_nrs.into_iter().zip(&blocks).zip(parsed) {
+            if current_file != Some(file_nr) {
+                diagnostic_count += report_diagnostics(&mut diagnostics);
+                let log_path = &cli_input.can_log_paths[file_nr];
+                log::info!(
+                    "Parsing {} log file {:#?} ({}/{})...",
+                    block.format,
+                    log_path.as_os_str(),
+                    file_nr + 1,
+                    cli_input.can_log_paths.len()
+                );
+                log_formats[file_nr] = Some(block.format);
+                // Files with their own date start there, others continue where the previous one ended.
+                if cli_input.start_date.is_none() && reference_date.is_some() {
+                    let own_date = block
+                        .format
+                        .start_date()
+                        .or_else(|| date_from_file_name(log_path));
+                    if let Some(date) = own_date {
+                        context =
+                            TimestampContext::starting_at(date).in_time_zone(cli_input.timezone);
+                    }
+                }
+                current_file = Some(file_nr);
+            }
+            for (hex_id, info) in parsed_block.id_infos {
+                if checked_ids.insert(hex_id) {
+                    pending_id_infos.push((hex_id, info));
+                }
+            }
+            for line in parsed_block.lines {
+                match line {
+                    Ok(log_line) => batch.push(log_line.resolve(&mut context)),
+                    Err(e) => diagnostics.push(e),
+                }
+                if batch.len() >= cli_input.batch_size {
+                    check_can_ids(
+                        &batch,
+                        &mut can_ids,
+                        &mut unknown_ids,
+                        &id_rules,
+                        Some(&mut diagnostics),
+                    );
+                    decode_values(&mut batch, &can_ids);
+                    cross_check_ids(
+                        &pending_id_infos,
+                        &mut can_ids,
+                        cli_input.fill_metadata,
+                        Some(&mut diagnostics),
+                    );
+                    pending_id_infos.clear();
+                    trailing_zeros = trailing_zeros.min(least_trailing_zeros(&batch));
+                    write_or_exit(writer.write_batch(&batch, &can_ids), output_path);
+                    batch.clear();
+                }
+            }
         }
     }
+    check_can_ids(
+        &batch,
+        &mut can_ids,
+        &mut unknown_ids,
+        &id_rules,
+        Some(&mut diagnostics),
+    );
+    decode_values(&mut batch, &can_ids);
+    cross_check_ids(
+        &pending_id_infos,
+        &mut can_ids,
+        cli_input.fill_metadata,
+        Some(&mut diagnostics),
+    );
+    trailing_zeros = trailing_zeros.min(least_trailing_zeros(&batch));
+    write_or_exit(writer.write_batch(&batch, &can_ids), output_path);
+    drop(batch);
+    diagnostic_count += report_diagnostics(&mut diagnostics);
+
+    let total_size_b: u64 = cli_input
+        .can_log_paths
+        .iter()
+        .filter_map(|log_path| std::fs::metadata(log_path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
 
     let mut can_cmts: Vec<CanCmt> = Vec::new();
     if let Some(comments_path) = &cli_input.comments_path {
         log::info!("Parsing comments from {:#?}...", comments_path.as_os_str());
-        can_cmts.append(&mut parse_comments(&comments_path));
+        can_cmts.append(&mut or_exit(parse_comments(
+            comments_path,
+            start_context,
+            Some(&mut diagnostics),
+        )));
+    }
+    diagnostic_count += report_diagnostics(&mut diagnostics);
+
+    if cli_input.strict && diagnostic_count > 0 {
+        log::error!("Aborting, as {diagnostic_count} problems were found in strict mode.");
+        let created_paths = writer.created_paths();
+        drop(writer);
+        // Only what the writer created is removed, never a directory which already existed.
+        for path in created_paths.iter().rev() {
+            let _ = if path.is_dir() {
+                std::fs::remove_dir(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+        }
+        exit(1);
     }
-
-    check_can_ids(&can_msgs, &mut can_ids);
-    can_msgs.sort();
 
     let end = SystemTime::now();
     let duration = end.duration_since(start).unwrap().as_millis();
     log::info!("Took {} ms", duration);
 
     let meta = CanMeta {
-        cli: &cli_input,
+        can_ids_path: &cli_input.can_ids_path,
+        comments_path: cli_input.comments_path.as_deref(),
+        log_paths: &cli_input.can_log_paths,
+        log_formats: &log_formats,
         time_ms: duration,
         old_size_b: total_size_b,
         least_trailing_zeros: trailing_zeros,
+        reference_date,
+        time_zone: cli_input.timezone,
     };
 
-    log::info!("Writing to {:#?}...", cli_input.output_path.as_os_str());
-    let collection = create_collection(&can_msgs, &can_ids);
-    let _ = write_to_hdf5(&cli_input.output_path, &collection, &can_cmts, &meta);
+    let dataset_count = writer.dataset_count();
+    write_or_exit(writer.finish(&can_cmts, &meta), output_path);
 
     log::debug!("Identified {} CAN IDs.", can_ids.len());
 
-    if collection.len() == 0 {
-        log::error!("Wrote 0 datasets! Maybe try using the parser for extended log files (-e)");
+    if dataset_count == 0 {
+        log::error!("Wrote 0 datasets! Check the detected log formats above.");
     } else {
-        log::info!("Wrote {} datasets.", collection.len());
+        log::info!("Wrote {} datasets.", dataset_count);
     }
 
-    let new_size_b = std::fs::metadata(&cli_input.output_path).unwrap().len();
+    if is_stdout(&output_path) {
+        return;
+    }
+    let new_size_b = output_size_b(output_path);
     log::info!(
         "{:?}: {} ({} B from {} B)",
-        cli_input.output_path.as_os_str(),
+        output_path.as_os_str(),
         humansize::format_size(new_size_b, humansize::DECIMAL),
         new_size_b,
         total_size_b
@@ -0,0 +1,161 @@
+use nom::{
+    bytes::complete::{tag, take, take_till, take_while_m_n},
+    character::{
+        complete::{digit0, digit1, hex_digit0, hex_digit1, space0, space1},
+        is_digit, is_space,
+    },
+    combinator::opt,
+    error::{ContextError, ErrorKind, ParseError},
+    sequence::{delimited, tuple},
+    IResult,
+};
+
+use super::common::{bytes_to_number, bytes_to_string, LogTime, Span, TIMESTAMP_CONTEXT};
+use super::tv_messages::{CanPayload, LogLine};
+
+/// Marks error frames in the CAN ID.
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+/// Most digits of the seconds which may still fit into nanoseconds as u64.
+const MAX_SECS_DIGITS: usize = 11;
+
+// (1697539200.123456)
+/// Digits of the fraction beyond nanoseconds are ignored.
+fn parse_unix_ts<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
+    input: Span<'a>,
+) -> IResult<Span<'a>, u64, E> {
+    let (r, (secs_raw, _, subsec_raw, _)) = nom::error::context(
+        TIMESTAMP_CONTEXT,
+        delimited(
+            tag("("),
+            tuple((digit1, tag("."), take_while_m_n(1, 9, is_digit), digit0)),
+            tag(")"),
+        ),
+    )(input)?;
+    let too_large = || {
+        nom::Err::Failure(E::add_context(
+            secs_raw,
+            TIMESTAMP_CONTEXT,
+            E::from_error_kind(secs_raw, ErrorKind::TooLarge),
+        ))
+    };
+    if secs_raw.len() > MAX_SECS_DIGITS {
+        return Err(too_large());
+    }
+    let (secs, _) = bytes_to_number(secs_raw);
+    let (subsec, digits) = bytes_to_number(subsec_raw);
+    let nanos = subsec * 10_u64.pow(9 - digits);
+    let ts = secs
+        .checked_mul(1_000_000_000)
+        .and_then(|ns| ns.checked_add(nanos))
+        .ok_or_else(too_large)?;
+    Ok((r, ts))
+}
+
+// (1697539200.123456) can0 10030001#2E00000001000000
+// (1697539200.123456) can0 123#R
+// (1697539200.123456) can0 10030001##1112233
+/// Parses a line written by `candump -l`. Remote and error frames are skipped.
+///
+/// The value of the messages is NaN, as it has to be decoded from the payload with the CAN ID's scale.
+pub fn parse_candump<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
+    line: Span<'a>,
+) -> IResult<Span<'a>, Option<LogLine>, E> {
+    let done = Span::new("".as_bytes());
+    let (rest, _) = space0(line)?;
+    if !rest.starts_with(b"(") {
+        return Ok((done, None));
+    }
+
+    let (rest, (ts, _, _, _, id_raw, _)) = tuple((
+        parse_unix_ts,
+        space1,
+        take_till(is_space),
+        space1,
+        hex_digit1,
+        tag("#"),
+    ))(rest)?;
+
+    let hex_id = match u32::from_str_radix(&bytes_to_string(id_raw), 16) {
+        Ok(hex_id) if hex_id & CAN_ERR_FLAG == 0 => hex_id,
+        Ok(_) => return Ok((done, None)),
+        Err(_) => {
+            return Err(nom::Err::Failure(E::from_error_kind(
+                id_raw,
+                ErrorKind::HexDigit,
+            )))
+        }
+    };
+    if rest.starts_with(b"R") {
+        return Ok((done, None));
+    }
+
+    // CAN FD frames have a second "#", followed by a flags nibble.
+    let (rest, _) = opt(tuple((tag("#"), take(1usize))))(rest)?;
+    let (rest, data_raw) = hex_digit0(rest)?;
+    if rest.first().is_some_and(|c| !c.is_ascii_whitespace()) {
+        return Err(nom::Err::Failure(E::from_error_kind(
+            rest,
+            ErrorKind::HexDigit,
+        )));
+    }
+
+    let hex = data_raw.fragment();
+    if hex.len() % 2 != 0 {
+        return Err(nom::Err::Failure(E::from_error_kind(
+            data_raw,
+            ErrorKind::HexDigit,
+        )));
+    }
+    let len = hex.len() / 2;
+    // Only the first 8 bytes of CAN FD frames are kept.
+    let mut data = [0u8; 8];
+    for (byte, digits) in data.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap();
+    }
+
+    Ok((
+        done,
+        Some(LogLine {
+            hex_id,
+            value: f32::NAN,
+            time: LogTime::Unix(ts),
+            payload: Some(CanPayload {
+                dlc: len.min(8) as u8,
+                data,
+            }),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+
+    fn parse_ts(ts: &str) -> Option<u64> {
+        parse_unix_ts::<VerboseError<Span>>(Span::new(ts.as_bytes()))
+            .ok()
+            .map(|(_, ts)| ts)
+    }
+
+    #[test]
+    fn parses_unix_timestamps() {
+        assert_eq!(
+            parse_ts("(1697539200.123456)"),
+            Some(1_697_539_200_123_456_000)
+        );
+        assert_eq!(parse_ts("(1.5)"), Some(1_500_000_000));
+        assert_eq!(
+            parse_ts("(1697539200.123456789999999999999)"),
+            Some(1_697_539_200_123_456_789)
+        );
+    }
+
+    #[test]
+    fn rejects_timestamps_beyond_u64_nanoseconds() {
+        assert!(parse_ts("(18446744074.0)").is_none());
+        assert!(parse_ts("(99999999999999999999999.0)").is_none());
+        assert!(parse_ts("(18446744073.709551615)").is_some());
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+use super::ParserError;
+
+/// Path standing for the standard input.
+pub const STDIN_PATH: &str = "-";
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+/// An input opened with [`open_input`], decompressed while reading.
+pub type InputReader = BufReader<Box<dyn Read + Send>>;
+
+pub fn is_stdin<P: AsRef<Path>>(path: &P) -> bool {
+    path.as_ref() == Path::new(STDIN_PATH)
+}
+
+/// Opens a file for reading, or the standard input for `-`.
+///
+/// Input compressed with gzip, zstd or xz is recognized by its first bytes and decompressed on the fly.
+pub fn open_input<P: AsRef<Path>>(path: &P) -> Result<InputReader, ParserError> {
+    let source: Box<dyn Read + Send> = if is_stdin(path) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(|e| ParserError::io(path, e))?)
+    };
+    decompress(BufReader::new(source), path)
+}
+
+/// Decompresses input compressed with gzip, zstd or xz, recognized by its first bytes, while reading.
+/// Other input is passed through.
+fn decompress<P: AsRef<Path>>(source: InputReader, path: &P) -> Result<InputReader, ParserError> {
+    let (magic, source) = peek_input(source, path, |sample| sample.len() >= XZ_MAGIC.len())?;
+
+    let reader: Box<dyn Read + Send> = if magic.starts_with(GZIP_MAGIC) {
+        log::debug!("Decompressing gzip input");
+        Box::new(MultiGzDecoder::new(source))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        log::debug!("Decompressing zstd input");
+        Box::new(zstd::Decoder::with_buffer(source).map_err(|e| ParserError::io(path, e))?)
+    } else if magic.starts_with(XZ_MAGIC) {
+        log::debug!("Decompressing xz input");
+        Box::new(XzDecoder::new_multi_decoder(source))
+    } else {
+        Box::new(source)
+    };
+    Ok(BufReader::new(reader))
+}
+
+/// Reads from `reader` until `enough` holds for the sample read or the input ends. Returns the sample
+/// along with a reader yielding it again, followed by the rest of the input.
+pub(crate) fn peek_input<P: AsRef<Path>>(
+    mut reader: InputReader,
+    path: &P,
+    enough: impl Fn(&[u8]) -> bool,
+) -> Result<(Vec<u8>, InputReader), ParserError> {
+    let mut sample = Vec::new();
+    while !enough(&sample) {
+        let buf = match reader.fill_buf() {
+            Ok([]) => break,
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ParserError::io(path, e)),
+        };
+        sample.extend_from_slice(buf);
+        let len = buf.len();
+        reader.consume(len);
+    }
+    let input: Box<dyn Read + Send> = Box::new(io::Cursor::new(sample.clone()).chain(reader));
+    Ok((sample, BufReader::new(input)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::tv_messages::can_log_reader::detect_format;
+    use crate::parsers::LogFormat;
+    use std::io::Write;
+
+    const LOG: &str = "(1697539200.123456) can0 10030001#2E00000001000000\n\
+                       (1697539200.223456) can0 10030001#2F00000001000000\n";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn in_memory(data: Vec<u8>) -> InputReader {
+        BufReader::new(Box::new(io::Cursor::new(data)))
+    }
+
+    /// Detects the format of the (decompressed) input and reads it all.
+    fn read_log(reader: InputReader) -> (LogFormat, String) {
+        let (mut reader, format) = detect_format(reader, &"log", None).unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        (format, text)
+    }
+
+    #[test]
+    fn decompresses_by_magic_bytes() {
+        let compressed = [
+            gzip(LOG.as_bytes()),
+            zstd::encode_all(LOG.as_bytes(), 0).unwrap(),
+            xz(LOG.as_bytes()),
+        ];
+        for data in compressed {
+            let (format, text) = read_log(decompress(in_memory(data), &"log").unwrap());
+            assert!(matches!(format, LogFormat::Candump { .. }));
+            assert_eq!(text, LOG);
+        }
+    }
+
+    #[test]
+    fn passes_plain_input_through() {
+        let (format, text) = read_log(decompress(in_memory(LOG.into()), &"log").unwrap());
+        assert!(matches!(format, LogFormat::Candump { .. }));
+        assert_eq!(text, LOG);
+
+        let (_, text) = read_log(decompress(in_memory(vec![0x1F]), &"log").unwrap());
+        assert_eq!(text.as_bytes(), [0x1F]);
+    }
+
+    #[test]
+    fn opens_compressed_files() {
+        let path = std::env::temp_dir().join(format!("can-parser-{}.log.gz", std::process::id()));
+        std::fs::write(&path, gzip(LOG.as_bytes())).unwrap();
+        let result = open_input(&path).map(read_log);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap().1, LOG);
+
+        assert!(matches!(open_input(&path), Err(ParserError::Io { .. })));
+    }
+
+    #[test]
+    fn takes_a_dash_for_the_standard_input() {
+        assert!(is_stdin(&STDIN_PATH));
+        assert!(is_stdin(&Path::new("-")));
+        assert!(!is_stdin(&"./-"));
+    }
+}
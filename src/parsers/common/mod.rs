@@ -1,20 +1,14 @@
-use miette::GraphicalReportHandler;
 use nom_locate::LocatedSpan;
-use nom_supreme::error::{BaseErrorKind, ErrorTree, GenericErrorTree};
 
-pub type Span<'a> = LocatedSpan<&'a [u8]>;
-
-#[derive(thiserror::Error, Debug, miette::Diagnostic)]
-#[error("Bad Input")]
-pub struct BadInput {
-    #[source_code]
-    pub src: String,
+pub mod input;
+pub mod parser_error;
+pub mod timestamp;
+pub(crate) use input::peek_input;
+pub use input::{is_stdin, open_input, InputReader, STDIN_PATH};
+pub use parser_error::{ParserError, TIMESTAMP_CONTEXT};
+pub use timestamp::{date_from_file_name, ClockTime, LogTime, RecordingTimeZone, TimestampContext};
 
-    #[label("{kind}")]
-    pub bad_bit: miette::SourceSpan,
-
-    pub kind: BaseErrorKind<&'static str, Box<dyn std::error::Error + Send + Sync>>,
-}
+pub type Span<'a> = LocatedSpan<&'a [u8]>;
 
 pub fn bytes_to_string<'a>(input: Span<'a>) -> String {
     String::from_utf8_lossy(input.as_ref()).trim().to_string()
@@ -33,26 +27,16 @@ pub fn bytes_to_number<'a>(input: Span<'a>) -> (u64, u32) {
     (number, digits)
 }
 
-pub fn handle_error<'a>(src: String, e: ErrorTree<Span<'a>>) {
-    match e {
-        GenericErrorTree::Base { location, kind } => {
-            let offset = location.location_offset().into();
-            let err = BadInput {
-                src,
-                bad_bit: miette::SourceSpan::new(offset, 0.into()),
-                kind,
-            };
-            let mut s = String::new();
-            GraphicalReportHandler::new()
-                .render_report(&mut s, &err)
-                .unwrap();
-            log::warn!("{s}");
-        }
-        GenericErrorTree::Stack { .. } => todo!("stack"),
-        GenericErrorTree::Alt(s) => {
-            for i in s {
-                handle_error(src.clone(), i);
-            }
-        }
+/// Parses a decimal number, which may use a comma as decimal separator.
+pub fn decimal_to_f32(input: Span) -> Option<f32> {
+    bytes_to_string(input).replace(',', ".").parse().ok()
+}
+
+/// Hands a per-line error to the diagnostics collected by the caller.
+/// If the caller did not ask for diagnostics, the error is logged instead.
+pub fn report_error(e: ParserError, diagnostics: &mut Option<&mut Vec<ParserError>>) {
+    match diagnostics {
+        Some(diagnostics) => diagnostics.push(e),
+        None => log::warn!("{}", e.render()),
     }
 }
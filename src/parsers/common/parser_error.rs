@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use miette::GraphicalReportHandler;
+use nom_supreme::error::{ErrorTree, GenericErrorTree, StackContext};
+
+use super::Span;
+
+/// Context attached to every timestamp parser, so that failures can be told apart from other bad input.
+pub const TIMESTAMP_CONTEXT: &str = "timestamp";
+
+#[derive(thiserror::Error, Debug, miette::Diagnostic)]
+pub enum ParserError {
+    #[error("Could not read {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Bad input in {path:?}, line {line_nr}")]
+    BadLine {
+        path: PathBuf,
+        line_nr: usize,
+        #[source_code]
+        src: String,
+        #[label("{kind}")]
+        span: miette::SourceSpan,
+        kind: String,
+    },
+
+    #[error("Bad object {object_nr} in {path:?}: {kind}")]
+    BadObject {
+        path: PathBuf,
+        object_nr: usize,
+        kind: String,
+    },
+
+    #[error("Bad configuration in {path:?}: {message}")]
+    BadConfig { path: PathBuf, message: String },
+
+    #[error("Unsupported {feature} in {path:?}")]
+    Unsupported { path: PathBuf, feature: String },
+
+    #[error("Unknown CAN ID {hex_id} (0x{hex_id:X})")]
+    UnknownId { hex_id: u32 },
+
+    #[error(
+        "The {field} of CAN ID 0x{hex_id:X} is {in_log:?} in {path:?}, line {line_nr}, but {in_header:?} in the CAN IDs file"
+    )]
+    MetadataMismatch {
+        hex_id: u32,
+        field: &'static str,
+        in_log: String,
+        in_header: String,
+        path: PathBuf,
+        line_nr: usize,
+    },
+
+    #[error("Invalid timestamp in {path:?}, line {line_nr}")]
+    InvalidTimestamp {
+        path: PathBuf,
+        line_nr: usize,
+        #[source_code]
+        src: String,
+        #[label("{kind}")]
+        span: miette::SourceSpan,
+        kind: String,
+    },
+}
+
+impl ParserError {
+    pub fn io<P: AsRef<Path>>(path: &P, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// Converts the error of a line parser into an owned error, pointing at the offending bit of the line.
+    pub fn from_tree<P: AsRef<Path>>(
+        path: &P,
+        line_nr: usize,
+        src: String,
+        e: ErrorTree<Span>,
+    ) -> Self {
+        let (offset, kind, is_timestamp) = locate(&e);
+        let path = path.as_ref().to_path_buf();
+        let src = src.trim_end().to_string();
+        let span = miette::SourceSpan::new(offset.into(), 0.into());
+        if is_timestamp {
+            Self::InvalidTimestamp {
+                path,
+                line_nr,
+                src,
+                span,
+                kind,
+            }
+        } else {
+            Self::BadLine {
+                path,
+                line_nr,
+                src,
+                span,
+                kind,
+            }
+        }
+    }
+
+    /// Renders the error including the source line, if there is one.
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        GraphicalReportHandler::new()
+            .render_report(&mut s, self)
+            .unwrap();
+        s
+    }
+}
+
+/// Finds the offset of the error within the line, a description of what went wrong,
+/// and whether a timestamp was being parsed.
+fn locate(e: &ErrorTree<Span>) -> (usize, String, bool) {
+    match e {
+        GenericErrorTree::Base { location, kind } => {
+            (location.location_offset(), kind.to_string(), false)
+        }
+        GenericErrorTree::Stack { base, contexts } => {
+            let (offset, kind, is_timestamp) = locate(base);
+            let in_timestamp = contexts
+                .iter()
+                .any(|(_, c)| matches!(c, StackContext::Context(TIMESTAMP_CONTEXT)));
+            (offset, kind, is_timestamp || in_timestamp)
+        }
+        GenericErrorTree::Alt(alternatives) => {
+            // Point at the alternative which got the furthest
+            let located: Vec<(usize, String, bool)> = alternatives.iter().map(locate).collect();
+            let offset = located.iter().map(|(o, _, _)| *o).max().unwrap_or(0);
+            let kind = located
+                .iter()
+                .map(|(_, k, _)| k.as_str())
+                .collect::<Vec<&str>>()
+                .join(" or ");
+            let is_timestamp = located.iter().any(|(_, _, t)| *t);
+            (offset, kind, is_timestamp)
+        }
+    }
+}
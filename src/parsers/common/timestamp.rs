@@ -0,0 +1,321 @@
+use chrono::{
+    DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use std::{fmt, path::Path, str::FromStr, time::UNIX_EPOCH};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * NANOS_PER_SEC;
+
+/// Time of day as found in the logs, possibly preceded by a day counter.
+///
+/// [0.|1.]HH:MM:SS.[0-9]{1..4}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockTime {
+    pub day: Option<u64>,
+    pub hour: u64,
+    pub min: u64,
+    pub sec: u64,
+    pub subsec: u64,
+    /// Number of digits `subsec` was given with.
+    pub digits: u32,
+}
+
+impl ClockTime {
+    /// Splits nanoseconds since midnight of the first day into days and time of day.
+    pub fn from_nanos(nanos: u64) -> Self {
+        let time_of_day = nanos % NANOS_PER_DAY;
+        Self {
+            day: Some(nanos / NANOS_PER_DAY),
+            hour: time_of_day / (NANOS_PER_SEC * 60 * 60),
+            min: time_of_day / (NANOS_PER_SEC * 60) % 60,
+            sec: time_of_day / NANOS_PER_SEC % 60,
+            subsec: time_of_day % NANOS_PER_SEC,
+            digits: 9,
+        }
+    }
+
+    /// Nanoseconds since midnight.
+    pub fn nanos(&self) -> u64 {
+        // Mostly, subsecond units are not given with the righ amount of
+        // digits and are therefore parsed wrong. Correct for that here.
+        let subsec_scalar: u64 = NANOS_PER_SEC / 10_u64.pow(self.digits);
+
+        (subsec_scalar * self.subsec)
+            + (NANOS_PER_SEC * self.sec)
+            + (NANOS_PER_SEC * 60 * self.min)
+            + (NANOS_PER_SEC * 60 * 60 * self.hour)
+    }
+}
+
+/// Time of a message as found in a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTime {
+    /// Time of day, which is resolved with the date kept by the [`TimestampContext`].
+    Clock(ClockTime),
+    /// Nanoseconds since the Unix epoch (UTC).
+    Unix(u64),
+}
+
+/// Time zone the clocks of a recording were set to, by default the local one (like the CLI's).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingTimeZone {
+    Utc,
+    /// Time zone of the machine running the conversion, including daylight saving time.
+    #[default]
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl RecordingTimeZone {
+    /// Converts a wall-clock time to UTC. Ambiguous times (when clocks are turned back) resolve to the
+    /// earlier instant, skipped times (when clocks are turned forward) are taken as if no skip happened.
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let resolved = match self {
+            Self::Utc => Some(Utc.from_utc_datetime(&local)),
+            Self::Local => Local
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Utc)),
+            Self::Fixed(offset) => offset
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Utc)),
+        };
+        resolved.unwrap_or_else(|| {
+            let offset = Local.offset_from_utc_datetime(&local);
+            Utc.from_utc_datetime(&(local - Duration::seconds(offset.local_minus_utc() as i64)))
+        })
+    }
+
+    /// Converts a point in time to the wall-clock time of this time zone.
+    pub fn to_local(&self, utc: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Self::Utc => utc.naive_utc(),
+            Self::Local => utc.with_timezone(&Local).naive_local(),
+            Self::Fixed(offset) => utc.with_timezone(offset).naive_local(),
+        }
+    }
+}
+
+impl fmt::Display for RecordingTimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Utc => write!(f, "UTC"),
+            Self::Local => write!(f, "local ({})", Local::now().offset()),
+            Self::Fixed(offset) => write!(f, "{offset}"),
+        }
+    }
+}
+
+impl FromStr for RecordingTimeZone {
+    type Err = String;
+
+    /// Accepts "UTC", "local" or a fixed offset like "+01:00".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utc" | "z" => return Ok(Self::Utc),
+            "local" => return Ok(Self::Local),
+            _ => {}
+        }
+        let invalid = || format!("Invalid time zone {s:?}, expected UTC, local or [+-]HH:MM");
+        let (sign, rest) = if let Some(rest) = s.strip_prefix('+') {
+            (1, rest)
+        } else if let Some(rest) = s.strip_prefix('-') {
+            (-1, rest)
+        } else {
+            return Err(invalid());
+        };
+        if !rest.chars().all(|c| c.is_ascii_digit() || c == ':') {
+            return Err(invalid());
+        }
+        let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+        let hours: u8 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u8 = minutes.parse().map_err(|_| invalid())?;
+        if minutes >= 60 {
+            return Err(invalid());
+        }
+        FixedOffset::east_opt(sign * (hours as i32 * 3600 + minutes as i32 * 60))
+            .map(Self::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Turns the clock times of a single file (or stream) into timestamps in nanoseconds.
+///
+/// Logs only carry the time of day, so the context keeps track of how many times midnight was passed.
+/// Without a start date, timestamps count from midnight of the first day. With a start date, they are
+/// absolute: nanoseconds since the Unix epoch, with the clock times taken in the given time zone.
+///
+/// To continue with a file recorded right after another one, clone the context the first file ended with.
+#[derive(Debug, Clone, Default)]
+pub struct TimestampContext {
+    start_date: Option<NaiveDate>,
+    time_zone: RecordingTimeZone,
+    last_hour: Option<u64>,
+    days: u64,
+}
+
+impl TimestampContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn starting_at(start_date: NaiveDate) -> Self {
+        Self {
+            start_date: Some(start_date),
+            ..Default::default()
+        }
+    }
+
+    /// Time zone of the clock times, local time unless set otherwise.
+    pub fn in_time_zone(mut self, time_zone: RecordingTimeZone) -> Self {
+        self.time_zone = time_zone;
+        self
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.start_date.is_some()
+    }
+
+    /// Number of times midnight was passed so far.
+    pub fn days(&self) -> u64 {
+        self.days
+    }
+
+    /// Date of the clock times resolved last, if the context has a start date.
+    pub fn current_date(&self) -> Option<NaiveDate> {
+        self.start_date
+            .map(|date| date + Duration::days(self.days as i64))
+    }
+
+    pub fn resolve(&mut self, time: &ClockTime) -> u64 {
+        // Logs might surpass midnight, which may or may not be inidcated by a preceeding "1." before the
+        // current time. Sometimes, a leading "0." indicates that we did not surpass midnight yet.
+        // If midnight is surpassed, "0." may or may not change to "1.".
+        // Without an indicator, the hour jumping back by half a day or more tells us.
+        if let Some(last_hour) = self.last_hour {
+            if time.hour + 12 <= last_hour {
+                self.days += 1;
+                log::debug!("Timestamps wrapped over at midnight!");
+                log::trace!(
+                    "\tLast hour: {last_hour}, now: {time:?} => day {}",
+                    self.days
+                );
+            }
+        }
+        if let Some(day) = time.day {
+            self.days = self.days.max(day);
+        }
+        self.last_hour = Some(time.hour);
+
+        match self.current_date() {
+            Some(date) => self.resolve_on(date, time),
+            None => self.days * NANOS_PER_DAY + time.nanos(),
+        }
+    }
+
+    /// Resolves the time of a log line, see [`TimestampContext::resolve`] and
+    /// [`TimestampContext::resolve_unix`].
+    pub fn resolve_time(&mut self, time: &LogTime) -> u64 {
+        match time {
+            LogTime::Clock(time) => self.resolve(time),
+            LogTime::Unix(nanos) => self.resolve_unix(*nanos),
+        }
+    }
+
+    /// Resolves a timestamp which is already absolute.
+    ///
+    /// An absolute context passes it on and moves its date along, so clock times resolved afterwards
+    /// continue from there. Otherwise, it is turned into a clock time in the context's time zone.
+    pub fn resolve_unix(&mut self, nanos: u64) -> u64 {
+        let utc = DateTime::<Utc>::from(UNIX_EPOCH + std::time::Duration::from_nanos(nanos));
+        let local = self.time_zone.to_local(utc);
+        let time = ClockTime::from_nanos(
+            local.num_seconds_from_midnight() as u64 * NANOS_PER_SEC + local.nanosecond() as u64,
+        );
+        match self.start_date {
+            Some(start_date) => {
+                self.days = (local.date() - start_date).num_days().max(0) as u64;
+                self.last_hour = Some(time.hour);
+                nanos
+            }
+            None => self.resolve(&ClockTime { day: None, ..time }),
+        }
+    }
+
+    /// Resolves a clock time on a known date, without affecting the rollover tracking.
+    /// Only makes sense for absolute contexts.
+    pub fn resolve_on(&self, date: NaiveDate, time: &ClockTime) -> u64 {
+        let local = date.and_hms_opt(0, 0, 0).unwrap() + Duration::nanoseconds(time.nanos() as i64);
+        let utc = self.time_zone.to_utc(local);
+        utc.timestamp() as u64 * 1_000_000_000 + utc.timestamp_subsec_nanos() as u64
+    }
+}
+
+/// Looks for a date in the name of a file, e.g. "2014-10-23_experiment.log" or "log_20141023.txt".
+pub fn date_from_file_name<P: AsRef<Path>>(path: &P) -> Option<NaiveDate> {
+    const FORMATS: [(usize, &str); 6] = [
+        (10, "%Y-%m-%d"),
+        (10, "%Y_%m_%d"),
+        (10, "%d.%m.%Y"),
+        (10, "%m-%d-%Y"),
+        (10, "%m_%d_%Y"),
+        (8, "%Y%m%d"),
+    ];
+    let name = path.as_ref().file_name()?.to_str()?;
+    let bytes = name.as_bytes();
+    for start in 0..bytes.len() {
+        // Dates must not be part of a longer number.
+        if start > 0 && bytes[start - 1].is_ascii_digit() {
+            continue;
+        }
+        for (len, format) in FORMATS {
+            let end = start + len;
+            if end > bytes.len() || bytes.get(end).is_some_and(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            if let Some(candidate) = name.get(start..end) {
+                if let Ok(date) = NaiveDate::parse_from_str(candidate, format) {
+                    return Some(date);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_zones() {
+        let fixed = |secs| RecordingTimeZone::Fixed(FixedOffset::east_opt(secs).unwrap());
+        assert_eq!("UTC".parse(), Ok(RecordingTimeZone::Utc));
+        assert_eq!("local".parse(), Ok(RecordingTimeZone::Local));
+        assert_eq!("+01:00".parse(), Ok(fixed(3600)));
+        assert_eq!("-05:30".parse(), Ok(fixed(-5 * 3600 - 30 * 60)));
+        assert_eq!("+2".parse(), Ok(fixed(2 * 3600)));
+    }
+
+    #[test]
+    fn rejects_invalid_time_zones() {
+        for s in [
+            "",
+            "+",
+            "01:00",
+            "\u{2212}01:00",
+            "é",
+            "++1:00",
+            "+01:60",
+            "+25:00",
+        ] {
+            assert!(s.parse::<RecordingTimeZone>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn defaults_to_local_time() {
+        assert_eq!(RecordingTimeZone::default(), RecordingTimeZone::Local);
+    }
+}
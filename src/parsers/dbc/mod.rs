@@ -0,0 +1,362 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till},
+    character::{
+        complete::{char, digit1, one_of, space0, space1},
+        is_space,
+    },
+    combinator::{map, map_res, opt},
+    error::{ErrorKind, FromExternalError, ParseError},
+    number::complete::double,
+    sequence::{delimited, preceded, tuple},
+    IResult,
+};
+use nom_supreme::{error::ErrorTree, final_parser::final_parser};
+use std::{collections::HashMap, io::BufRead, path::Path};
+
+use super::common::{bytes_to_string, open_input, report_error, ParserError, Span};
+use super::tv_id_headers::{ByteOrder, CanId, CanSignal, Multiplexing, ValueType};
+
+/// Flag of extended (29 bit) IDs in DBC files.
+const EXTENDED_ID_FLAG: u32 = 0x8000_0000;
+/// Message holding signals which are not sent in any message.
+const INDEPENDENT_SIGNALS: &str = "VECTOR__INDEPENDENT_SIG_MSG";
+
+fn parse_number<'a, E>(input: Span<'a>) -> IResult<Span<'a>, u32, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    map_res(digit1, |raw| bytes_to_string(raw).parse::<u32>())(input)
+}
+
+fn parse_name<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, String, E> {
+    map(take_till(|c| is_space(c) || c == b':'), bytes_to_string)(input)
+}
+
+fn parse_quoted<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, String, E> {
+    map(
+        delimited(char('"'), take_till(|c| c == b'"'), char('"')),
+        bytes_to_string,
+    )(input)
+}
+
+fn non_empty(text: String) -> Option<String> {
+    (!text.is_empty()).then_some(text)
+}
+
+/// A line of a DBC file, as far as it is of interest.
+enum DbcLine {
+    /// ID and name of a message
+    Message(u32, String),
+    Signal(CanSignal),
+    /// ID of a message (and name of a signal) with its description
+    Comment(u32, Option<String>, String),
+    /// ID of a message and name of a signal with its value type
+    ValueType(u32, String, ValueType),
+}
+
+// BO_ 2416115713 PRESSURE_SIG2: 8 Monitor
+fn parse_message<'a, E>(input: Span<'a>) -> IResult<Span<'a>, DbcLine, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (_, (_, _, id, _, name, _, _)) = tuple((
+        tag("BO_"),
+        space1,
+        parse_number,
+        space1,
+        parse_name,
+        space0,
+        char(':'),
+    ))(input)?;
+    Ok((Span::new("".as_bytes()), DbcLine::Message(id, name)))
+}
+
+// M, m3 or m3M (a multiplexed signal which is a multiplexor itself, multiplexing further signals is not
+// supported)
+fn parse_multiplexing<'a, E>(input: Span<'a>) -> IResult<Span<'a>, Multiplexing, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    alt((
+        map(char('M'), |_| Multiplexing::Multiplexor),
+        map(
+            tuple((
+                char('m'),
+                map_res(digit1, |raw| bytes_to_string(raw).parse::<u64>()),
+                opt(char('M')),
+            )),
+            |(_, value, _)| Multiplexing::Multiplexed(value),
+        ),
+    ))(input)
+}
+
+//  SG_ Pressure : 0|32@1- (0.01,0) [-500|500] "mmHg" Monitor
+//  SG_ Mode M : 0|8@1+ (1,0) [0|255] "" Monitor
+//  SG_ Flow m1 : 8|16@1+ (0.01,0) [0|100] "L/min" Monitor
+fn parse_signal<'a, E>(input: Span<'a>) -> IResult<Span<'a>, DbcLine, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (r, (_, _, name, multiplexing, _, _)) = tuple((
+        tag("SG_"),
+        space1,
+        parse_name,
+        opt(preceded(space1, parse_multiplexing)),
+        space0,
+        char(':'),
+    ))(input)?;
+    let (r, (_, start_bit, _, length, _, byte_order, sign)) = tuple((
+        space0,
+        parse_number,
+        char('|'),
+        parse_number,
+        char('@'),
+        alt((char('0'), char('1'))),
+        alt((char('+'), char('-'))),
+    ))(r)?;
+    let (r, (_, _, factor, _, offset, _)) =
+        tuple((space0, char('('), double, char(','), double, char(')')))(r)?;
+    let (r, (_, _, min, _, max, _)) =
+        tuple((space0, char('['), double, char('|'), double, char(']')))(r)?;
+    let (_, unit) = preceded(space0, parse_quoted)(r)?;
+
+    let signal = CanSignal {
+        name,
+        start_bit: start_bit as u16,
+        length: length as u16,
+        byte_order: match byte_order {
+            '0' => ByteOrder::BigEndian,
+            _ => ByteOrder::LittleEndian,
+        },
+        signed: sign == '-',
+        value_type: ValueType::Integer,
+        factor,
+        offset,
+        min,
+        max,
+        unit: non_empty(unit),
+        description: None,
+        multiplexing: multiplexing.unwrap_or_default(),
+    };
+    Ok((Span::new("".as_bytes()), DbcLine::Signal(signal)))
+}
+
+// CM_ BO_ 2416115713 "Pressure signal 2";
+// CM_ SG_ 2416115713 Pressure "Pressure at the second sensor";
+fn parse_comment<'a, E>(input: Span<'a>) -> IResult<Span<'a>, Option<DbcLine>, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (r, (_, _, kind, _)) = tuple((
+        tag("CM_"),
+        space1,
+        opt(alt((tag("BO_"), tag("SG_")))),
+        space0,
+    ))(input)?;
+    let Some(kind) = kind else {
+        // Comments of the network and of nodes
+        return Ok((Span::new("".as_bytes()), None));
+    };
+    let (r, id) = parse_number(r)?;
+    let (r, signal) = if *kind.fragment() == b"SG_" {
+        let (r, name) = preceded(space1, parse_name)(r)?;
+        (r, Some(name))
+    } else {
+        (r, None)
+    };
+    let (_, text) = preceded(space0, parse_quoted)(r)?;
+    Ok((
+        Span::new("".as_bytes()),
+        Some(DbcLine::Comment(id, signal, text)),
+    ))
+}
+
+// SIG_VALTYPE_ 2416115713 Pressure : 1;
+fn parse_value_type<'a, E>(input: Span<'a>) -> IResult<Span<'a>, DbcLine, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (_, (_, _, id, _, name, _, _, _, value_type)) = tuple((
+        tag("SIG_VALTYPE_"),
+        space1,
+        parse_number,
+        space1,
+        parse_name,
+        space0,
+        char(':'),
+        space0,
+        one_of("012"),
+    ))(input)?;
+    let value_type = match value_type {
+        '1' => ValueType::Float,
+        '2' => ValueType::Double,
+        _ => ValueType::Integer,
+    };
+    Ok((
+        Span::new("".as_bytes()),
+        DbcLine::ValueType(id, name, value_type),
+    ))
+}
+
+fn parse_line<'a, E>(line: Span<'a>) -> IResult<Span<'a>, Option<DbcLine>, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let done = Span::new("".as_bytes());
+    let (line, _) = space0(line)?;
+    if line.starts_with(b"BO_ ") {
+        let (_, message) = parse_message(line)?;
+        Ok((done, Some(message)))
+    } else if line.starts_with(b"SG_ ") {
+        let (_, signal) = parse_signal(line)?;
+        Ok((done, Some(signal)))
+    } else if line.starts_with(b"CM_ ") {
+        let (_, comment) = parse_comment(line)?;
+        Ok((done, comment))
+    } else if line.starts_with(b"SIG_VALTYPE_ ") {
+        let (_, value_type) = parse_value_type(line)?;
+        Ok((done, Some(value_type)))
+    } else {
+        Ok((done, None))
+    }
+}
+
+/// Parses a DBC file into the CAN IDs of its messages, along with their signals.
+///
+/// Messages with a single signal get its factor as scale and its unit, so their values are stored like
+/// those of IDs from a SmartECLA_IDs.h file. Extended IDs are given without the flag marking them.
+///
+/// Lines which cannot be parsed are pushed to `diagnostics` or, if no diagnostics are collected, logged.
+/// Failing to read the file is an error.
+pub fn parse_dbc<P: AsRef<Path>>(
+    dbc_file: &P,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) -> Result<Vec<CanId>, ParserError> {
+    let mut messages: Vec<(u32, String, Vec<CanSignal>)> = vec![];
+    let mut comments: HashMap<(u32, Option<String>), String> = HashMap::new();
+    let mut value_types: HashMap<(u32, String), ValueType> = HashMap::new();
+    let mut reader = open_input(dbc_file)?;
+    let mut line_buf = vec![];
+    let mut lnr = 0;
+    loop {
+        line_buf.clear();
+        match reader.read_until(b'\n', &mut line_buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return Err(ParserError::io(dbc_file, e)),
+        }
+        lnr += 1;
+        let first_lnr = lnr;
+        // Comments may span several lines, until their quotes are closed.
+        while line_buf.iter().filter(|c| **c == b'"').count() % 2 == 1 {
+            match reader.read_until(b'\n', &mut line_buf) {
+                Ok(0) => break,
+                Ok(_) => lnr += 1,
+                Err(e) => return Err(ParserError::io(dbc_file, e)),
+            }
+        }
+
+        let parse: Result<_, ErrorTree<Span>> =
+            final_parser(parse_line::<ErrorTree<Span>>)(Span::new(&line_buf[..]));
+        let src = || String::from_utf8_lossy(&line_buf).to_string();
+        match parse {
+            Ok(Some(DbcLine::Message(id, name))) => messages.push((id, name, vec![])),
+            Ok(Some(DbcLine::Signal(signal))) => match messages.last_mut() {
+                Some((_, _, signals)) => signals.push(signal),
+                None => report_error(
+                    ParserError::from_tree(
+                        dbc_file,
+                        first_lnr,
+                        src(),
+                        ErrorTree::from_error_kind(Span::new(&line_buf[..]), ErrorKind::Verify),
+                    ),
+                    &mut diagnostics,
+                ),
+            },
+            Ok(Some(DbcLine::Comment(id, signal, text))) => {
+                comments.insert((id, signal), text);
+            }
+            Ok(Some(DbcLine::ValueType(id, signal, value_type))) => {
+                value_types.insert((id, signal), value_type);
+            }
+            Ok(None) => {}
+            Err(e) => report_error(
+                ParserError::from_tree(dbc_file, first_lnr, src(), e),
+                &mut diagnostics,
+            ),
+        }
+    }
+
+    let mut can_ids = vec![];
+    for (id, name, mut signals) in messages {
+        if name == INDEPENDENT_SIGNALS {
+            continue;
+        }
+        for signal in signals.iter_mut() {
+            signal.description = comments.remove(&(id, Some(signal.name.clone())));
+            if let Some(value_type) = value_types.remove(&(id, signal.name.clone())) {
+                signal.value_type = value_type;
+            }
+        }
+        let mut can_id = CanId {
+            hex_id: id & !EXTENDED_ID_FLAG,
+            str_id: Some(name),
+            description: comments.remove(&(id, None)),
+            ..Default::default()
+        };
+        if let [signal] = &signals[..] {
+            can_id.scale = Some(signal.factor as f32);
+            can_id.unit = signal.unit.clone();
+            can_id.description = can_id.description.or(signal.description.clone());
+        }
+        can_id.signals = signals;
+        can_ids.push(can_id);
+    }
+
+    log::debug!("Parsed {} CAN IDs from a DBC file.", can_ids.len());
+    Ok(can_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+
+    fn parse(line: &str) -> Option<DbcLine> {
+        parse_line::<VerboseError<Span>>(Span::new(line.as_bytes()))
+            .ok()
+            .and_then(|(_, line)| line)
+    }
+
+    #[test]
+    fn parses_signal_value_types() {
+        match parse("SIG_VALTYPE_ 2416115713 Pressure : 1;") {
+            Some(DbcLine::ValueType(id, name, value_type)) => {
+                assert_eq!(id, 2416115713);
+                assert_eq!(name, "Pressure");
+                assert_eq!(value_type, ValueType::Float);
+            }
+            _ => panic!("value type not parsed"),
+        }
+        assert!(matches!(
+            parse("SIG_VALTYPE_ 1 Energy: 2 ;"),
+            Some(DbcLine::ValueType(1, _, ValueType::Double))
+        ));
+        assert!(parse("SIG_VALTYPE_ 1 Energy : 3;").is_none());
+    }
+
+    #[test]
+    fn parses_signals_as_integers() {
+        match parse(r#" SG_ Temp : 23|12@0- (0.1,-40) [-40|125] "degC" Vector__XXX"#) {
+            Some(DbcLine::Signal(signal)) => {
+                assert_eq!(signal.start_bit, 23);
+                assert_eq!(signal.length, 12);
+                assert_eq!(signal.byte_order, ByteOrder::BigEndian);
+                assert!(signal.signed);
+                assert_eq!(signal.value_type, ValueType::Integer);
+            }
+            _ => panic!("signal not parsed"),
+        }
+    }
+}
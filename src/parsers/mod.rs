@@ -1,8 +1,27 @@
+mod candump;
 mod common;
+mod dbc;
+mod peak_trc;
 mod tv_comments;
 mod tv_id_headers;
 mod tv_messages;
+mod vector_asc;
+mod vector_blf;
 
-pub use tv_comments::{parse_comments, CanCmt};
-pub use tv_id_headers::{parse_canids, CanId};
-pub use tv_messages::{parse_messages, CanMsg};
+pub(crate) use common::report_error;
+pub use common::{
+    date_from_file_name, is_stdin, open_input, ClockTime, InputReader, LogTime, ParserError,
+    RecordingTimeZone, TimestampContext, STDIN_PATH,
+};
+pub use dbc::parse_dbc;
+pub use peak_trc::{TrcHeader, TrcLayout};
+pub use tv_comments::{parse_comments, parse_comments_date, CanCmt};
+pub use tv_id_headers::{
+    parse_canids, ByteOrder, CanId, CanSignal, IdsFormat, Multiplexing, ValueType,
+};
+pub use tv_messages::{
+    parse_blocks, parse_id_info, parse_log_line, parse_messages, CanLogReader, CanMsg, CanPayload,
+    LogBlock, LogBlockReader, LogFormat, LogIdInfo, LogLine, ParsedBlock,
+};
+pub use vector_asc::AscHeader;
+pub use vector_blf::{parse_blf_objects, BlfHeader};
@@ -0,0 +1,197 @@
+use chrono::Timelike;
+use nom::{
+    bytes::complete::take_while,
+    character::{complete::space1, is_space},
+    error::{ContextError, ErrorKind, ParseError},
+    multi::separated_list1,
+    IResult,
+};
+
+use super::common::{bytes_to_string, ClockTime, LogTime, Span, TIMESTAMP_CONTEXT};
+use super::tv_messages::{CanPayload, LogLine};
+
+pub mod trc_header;
+pub use trc_header::{TrcHeader, TrcLayout};
+
+/// Number of data bytes of CAN FD frames by their DLC.
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Milliseconds since the start of the trace, e.g. "1841.0" or "1059.900".
+fn parse_offset(raw: Span) -> Option<u64> {
+    let text = bytes_to_string(raw);
+    let (millis, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    if millis.is_empty() || !(millis.bytes().chain(fraction.bytes())).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let fraction = &fraction[..fraction.len().min(6)];
+    let subms: u64 = format!("{fraction:0<6}").parse().ok()?;
+    Some(millis.parse::<u64>().ok()? * 1_000_000 + subms)
+}
+
+fn start_of_trace(header: &TrcHeader) -> u64 {
+    match header.start {
+        Some(start) => {
+            start.num_seconds_from_midnight() as u64 * 1_000_000_000 + start.nanosecond() as u64
+        }
+        None => 0,
+    }
+}
+
+//      1)      1841.0  Rx         0001  8  00 00 00 00 00 00 00 00
+//      1      1841.000 DT 1      0001 Rx - 8    00 00 00 00 00 00 00 00
+/// Parses a row of a PEAK PCAN-View trace. Only data frames yield messages, comments, remote frames and
+/// status or error rows are skipped.
+///
+/// The value of the messages is NaN, as it has to be decoded from the payload with the CAN ID's scale.
+pub fn parse_trc<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
+    line: Span<'a>,
+    header: &TrcHeader,
+) -> IResult<Span<'a>, Option<LogLine>, E> {
+    let done = Span::new("".as_bytes());
+    let (_, list) = separated_list1(space1, take_while(|c| !is_space(c)))(line)?;
+    let list: Vec<Span> = list
+        .into_iter()
+        .filter(|token| !token.trim_ascii().is_empty())
+        .collect();
+    let layout = header.layout;
+
+    if list.first().is_none_or(|first| first.starts_with(b";")) || list.len() <= layout.len {
+        return Ok((done, None));
+    }
+    if let Some(kind) = layout.kind {
+        // Version 1 names the direction, version 2 the kind of frame.
+        match bytes_to_string(list[kind]).as_str() {
+            "Rx" | "Tx" | "DT" | "FD" | "FB" | "FE" | "BI" => {}
+            _ => return Ok((done, None)),
+        }
+    }
+    if list
+        .get(layout.data)
+        .is_some_and(|data| bytes_to_string(*data) == "RTR")
+    {
+        return Ok((done, None));
+    }
+
+    let Some(offset) = parse_offset(list[layout.offset]) else {
+        let raw = list[layout.offset];
+        return Err(nom::Err::Failure(E::add_context(
+            raw,
+            TIMESTAMP_CONTEXT,
+            E::from_error_kind(raw, ErrorKind::Float),
+        )));
+    };
+    let hex_id = match u32::from_str_radix(&bytes_to_string(list[layout.id]), 16) {
+        Ok(hex_id) => hex_id,
+        Err(_) => {
+            return Err(nom::Err::Failure(E::from_error_kind(
+                list[layout.id],
+                ErrorKind::HexDigit,
+            )))
+        }
+    };
+    let len = match bytes_to_string(list[layout.len]).parse::<usize>() {
+        Ok(dlc) if layout.len_is_dlc && dlc < FD_LENGTHS.len() => Some(FD_LENGTHS[dlc]),
+        Ok(len) if !layout.len_is_dlc => Some(len),
+        _ => None,
+    };
+    let len = match len {
+        Some(len) if list.len() >= layout.data + len => len,
+        _ => {
+            return Err(nom::Err::Failure(E::from_error_kind(
+                list[layout.len],
+                ErrorKind::Digit,
+            )))
+        }
+    };
+
+    // Only the first 8 bytes of CAN FD frames are kept.
+    let mut data = [0u8; 8];
+    for (byte, raw) in data.iter_mut().zip(&list[layout.data..layout.data + len]) {
+        *byte = match u8::from_str_radix(&bytes_to_string(*raw), 16) {
+            Ok(byte) => byte,
+            Err(_) => {
+                return Err(nom::Err::Failure(E::from_error_kind(
+                    *raw,
+                    ErrorKind::HexDigit,
+                )))
+            }
+        };
+    }
+
+    Ok((
+        done,
+        Some(LogLine {
+            hex_id,
+            value: f32::NAN,
+            time: LogTime::Clock(ClockTime::from_nanos(start_of_trace(header) + offset)),
+            payload: Some(CanPayload {
+                dlc: len.min(8) as u8,
+                data,
+            }),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+
+    fn parse_row(version: &str, row: &str) -> LogLine {
+        let header = TrcHeader::parse(format!(";$FILEVERSION={version}\n").as_bytes()).unwrap();
+        parse_trc::<VerboseError<Span>>(Span::new(row.as_bytes()), &header)
+            .unwrap()
+            .1
+            .unwrap()
+    }
+
+    fn assert_frame(line: &LogLine, hex_id: u32, data: &[u8]) {
+        assert_eq!(line.hex_id, hex_id);
+        let payload = line.payload.unwrap();
+        assert_eq!(payload.dlc as usize, data.len());
+        assert_eq!(payload.bytes(), data);
+    }
+
+    #[test]
+    fn parses_version_1_1_rows() {
+        let line = parse_row(
+            "1.1",
+            "     1)      1841.0  Rx     100C0000  4  2E 00 00 01",
+        );
+        assert_frame(&line, 0x100C0000, &[0x2E, 0, 0, 1]);
+    }
+
+    #[test]
+    fn parses_version_1_2_rows_without_reserved_column() {
+        let line = parse_row(
+            "1.2",
+            "     1)      1841.0 1  Rx        0001  8  00 11 22 33 44 55 66 77",
+        );
+        assert_frame(&line, 1, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+        let line = parse_row("1.2", "     2)      1842.0 1  Rx        0002  2  AA BB");
+        assert_frame(&line, 2, &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn parses_version_1_3_rows_with_reserved_column() {
+        let line = parse_row(
+            "1.3",
+            "     1)      1841.0 1  Rx        0001 -  8  00 11 22 33 44 55 66 77",
+        );
+        assert_frame(&line, 1, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+        let line = parse_row("1.3", "     2)      1842.0 1  Rx        0002 -  2  AA BB");
+        assert_frame(&line, 2, &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn parses_version_2_1_rows() {
+        let header = ";$FILEVERSION=2.1\n;$COLUMNS=N,O,T,B,I,d,R,L,D\n";
+        let header = TrcHeader::parse(header.as_bytes()).unwrap();
+        let row = "      1      1841.000 DT 1      0001 Rx - 3    01 02 03";
+        let line = parse_trc::<VerboseError<Span>>(Span::new(row.as_bytes()), &header)
+            .unwrap()
+            .1
+            .unwrap();
+        assert_frame(&line, 1, &[1, 2, 3]);
+    }
+}
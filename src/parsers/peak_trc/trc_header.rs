@@ -0,0 +1,121 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Number of lines at the start of a trace searched for its header.
+const HEADER_LINES: usize = 40;
+
+/// Position of the columns used from the rows of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrcLayout {
+    pub offset: usize,
+    pub kind: Option<usize>,
+    pub id: usize,
+    pub len: usize,
+    /// Whether the length column holds the DLC rather than the number of data bytes.
+    pub len_is_dlc: bool,
+    pub data: usize,
+}
+
+impl TrcLayout {
+    /// Builds the layout from the column letters of the `$COLUMNS` header, e.g. "N,O,T,B,I,d,R,L,D".
+    pub fn from_columns(columns: &str) -> Option<Self> {
+        let letters: Vec<char> = columns
+            .chars()
+            .filter(|c| c.is_ascii_alphabetic())
+            .collect();
+        let find = |letter| letters.iter().position(|c| *c == letter);
+        let (len, len_is_dlc) = match (find('L'), find('l')) {
+            (Some(len), _) => (len, false),
+            (None, Some(dlc)) => (dlc, true),
+            (None, None) => return None,
+        };
+        Some(Self {
+            offset: find('O')?,
+            kind: find('T'),
+            id: find('I')?,
+            len,
+            len_is_dlc,
+            data: find('D')?,
+        })
+    }
+}
+
+/// Settings from the header of a PEAK PCAN-View trace.
+///
+/// ```text
+/// ;$FILEVERSION=2.1
+/// ;$STARTTIME=43788.6458170718
+/// ;$COLUMNS=N,O,T,B,I,d,R,L,D
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrcHeader {
+    pub version: (u8, u8),
+    /// Start of the trace, which the offsets of the rows are relative to.
+    pub start: Option<NaiveDateTime>,
+    pub layout: TrcLayout,
+}
+
+/// `$STARTTIME` is given in days since 1899-12-30, as OLE automation dates are.
+fn parse_ole_date(raw: &str) -> Option<NaiveDateTime> {
+    let days: f64 = raw.trim().parse().ok()?;
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)?.and_hms_opt(0, 0, 0)?;
+    epoch.checked_add_signed(Duration::microseconds(
+        (days * 86_400_000_000.0).round() as i64
+    ))
+}
+
+/// Version 1.0 traces only state the start in a comment: ";   Start time: 11/20/2019 15:30:00.595".
+fn parse_start_comment(raw: &str) -> Option<NaiveDateTime> {
+    let mut words = raw.split_whitespace();
+    let (date, time) = (words.next()?, words.next()?);
+    // Version 1.1 appends the tenth of milliseconds, as in "15:30:00.595.0".
+    let time = match time.match_indices('.').nth(1) {
+        Some((second_dot, _)) => &time[..second_dot],
+        None => time,
+    };
+    NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%m/%d/%Y %H:%M:%S%.f").ok()
+}
+
+impl TrcHeader {
+    /// Reads the header from the beginning of a trace, `None` if it is not a trace.
+    pub fn parse(sample: &[u8]) -> Option<TrcHeader> {
+        let mut version = None;
+        let mut start = None;
+        let mut start_comment = None;
+        let mut columns = None;
+        let text = String::from_utf8_lossy(sample);
+        for line in text.lines().take(HEADER_LINES) {
+            let line = line.trim();
+            if let Some(raw) = line.strip_prefix(";$FILEVERSION=") {
+                let (major, minor) = raw.trim().split_once('.')?;
+                version = Some((major.parse().ok()?, minor.parse().ok()?));
+            } else if let Some(raw) = line.strip_prefix(";$STARTTIME=") {
+                start = parse_ole_date(raw);
+            } else if let Some(raw) = line.strip_prefix(";$COLUMNS=") {
+                columns = Some(raw.to_string());
+            } else if let Some((_, raw)) = line.split_once("Start time:") {
+                start_comment = parse_start_comment(raw);
+                version.get_or_insert((1, 0));
+            }
+        }
+
+        let version = version?;
+        let layout = match (version, columns) {
+            (_, Some(columns)) => TrcLayout::from_columns(&columns)?,
+            ((1, 0), None) => TrcLayout::from_columns("NOIlD")?,
+            ((1, 1), None) => TrcLayout::from_columns("NOTIlD")?,
+            ((1, 2), None) => TrcLayout::from_columns("NOBTIlD")?,
+            ((1, _), None) => TrcLayout::from_columns("NOBTIRlD")?,
+            ((2, 0), None) => TrcLayout::from_columns("NOTIdlD")?,
+            (_, None) => TrcLayout::from_columns("NOTBIdRLD")?,
+        };
+        Some(TrcHeader {
+            version,
+            start: start.or(start_comment),
+            layout,
+        })
+    }
+
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        self.start.map(|start| start.date())
+    }
+}
@@ -1,37 +1,93 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::BufRead;
 use std::path::Path;
-use std::process::exit;
 
-use nom::bytes::complete::{is_a, tag, take_while};
+use chrono::NaiveDate;
+use hdf5::types::VarLenUnicode;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while};
 use nom::character::complete::{digit1, multispace1};
 use nom::character::is_newline;
-use nom::error::ParseError;
-use nom::multi::{many0, separated_list1};
-use nom::sequence::tuple;
+use nom::combinator::{map_res, opt};
+use nom::error::{ErrorKind, FromExternalError, ParseError};
+use nom::sequence::{terminated, tuple};
 use nom::IResult;
 use nom_supreme::error::ErrorTree;
 use nom_supreme::final_parser::final_parser;
 
-use super::common::{bytes_to_number, bytes_to_string, handle_error, Span};
+use super::common::{
+    bytes_to_string, open_input, report_error, ClockTime, ParserError, Span, TimestampContext,
+};
 
 pub mod tv_comment;
 pub use tv_comment::CanCmt;
 
-fn parse_id<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, u32, E> {
-    let (r, id_raw) = digit1(input)?;
-    let id: u32 = bytes_to_string(id_raw).parse().unwrap();
-    return Ok((r, id));
+fn parse_id<'a, E>(input: Span<'a>) -> IResult<Span<'a>, u32, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    map_res(digit1, |id_raw| bytes_to_string(id_raw).parse::<u32>())(input)
 }
 
-fn parse_time<'a, E: ParseError<Span<'a>>>(
-    input: Span<'a>,
-) -> IResult<Span<'a>, (u64, u64, u64), E> {
-    let (r, list) = separated_list1(tag(":"), digit1)(input)?;
-    let (hour, _) = bytes_to_number(list[0]);
-    let (min, _) = bytes_to_number(list[1]);
-    let (sec, _) = bytes_to_number(list[2]);
-    Ok((r, (hour, min, sec)))
+/// A number along with its count of digits. Numbers too large for a u32 fail the whole line, instead
+/// of overflowing.
+fn parse_number<'a, E>(input: Span<'a>) -> IResult<Span<'a>, (u32, usize), E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (r, raw) = digit1(input)?;
+    match bytes_to_string(raw).parse::<u32>() {
+        Ok(nr) => Ok((r, (nr, raw.len()))),
+        Err(e) => Err(nom::Err::Failure(E::from_external_error(
+            raw,
+            ErrorKind::Digit,
+            e,
+        ))),
+    }
+}
+
+// 10-23-2014, 23.10.2014 or 2014-10-23
+fn parse_date<'a, E>(input: Span<'a>) -> IResult<Span<'a>, NaiveDate, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (r, ((first_nr, first_digits), sep, (second_nr, _), _, (third_nr, _))) = tuple((
+        parse_number,
+        alt((tag("-"), tag("."))),
+        parse_number,
+        alt((tag("-"), tag("."))),
+        parse_number,
+    ))(input)?;
+    let (year, month, day) = if first_digits == 4 {
+        (first_nr, second_nr, third_nr)
+    } else if *sep.fragment() == b"." {
+        (third_nr, second_nr, first_nr)
+    } else {
+        (third_nr, first_nr, second_nr)
+    };
+    match NaiveDate::from_ymd_opt(year as i32, month, day) {
+        Some(date) => Ok((r, date)),
+        None => Err(nom::Err::Error(E::from_error_kind(
+            input,
+            ErrorKind::MapOpt,
+        ))),
+    }
+}
+
+fn parse_time<'a, E>(input: Span<'a>) -> IResult<Span<'a>, ClockTime, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
+    let (r, ((hour, _), _, (min, _), _, (sec, _))) =
+        tuple((parse_number, tag(":"), parse_number, tag(":"), parse_number))(input)?;
+    Ok((
+        r,
+        ClockTime {
+            hour: hour.into(),
+            min: min.into(),
+            sec: sec.into(),
+            ..Default::default()
+        },
+    ))
 }
 
 fn parse_content<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, String, E> {
@@ -40,115 +96,177 @@ fn parse_content<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'
     Ok((r, content))
 }
 
-fn parse_comment<'a>(
-    input: Span<'a>,
-) -> IResult<
+type RawComment<'a> = (
+    u32,
+    Span<'a>,
+    Option<NaiveDate>,
+    ClockTime,
     Span<'a>,
-    (
-        u32,
-        Span<'a>,
-        Vec<Span<'a>>,
-        Span<'a>,
-        (u64, u64, u64),
-        Span<'a>,
-        String,
-    ),
-> {
+    String,
+);
+
+fn parse_comment<'a, E>(input: Span<'a>) -> IResult<Span<'a>, RawComment<'a>, E>
+where
+    E: ParseError<Span<'a>> + FromExternalError<Span<'a>, std::num::ParseIntError>,
+{
     tuple((
         parse_id,
         multispace1,
-        many0(is_a("0123456789:.-")),
-        multispace1,
+        opt(terminated(parse_date, multispace1)),
         parse_time,
         multispace1,
         parse_content,
     ))(input)
 }
 
-fn to_timestamp(
-    hour: u64,
-    min: u64,
-    second: u64,
-    subsec: u64,
-    digits: u32,
-    did_surpass_midnight: bool,
-) -> u64 {
-    // Measure all in nanoseconds
-    static SUBSEC_UNIT: u64 = 1_000_000_000;
-    // Hour modifier, if we surpassed midnight.
-    let modifier: u64 = if did_surpass_midnight { 24 } else { 0 };
-    // Mostly, subsecond units are not given with the righ amount of
-    // digits and are therefore parsed wrong. Correct for that here.
-    let millis_scalar: u64 = SUBSEC_UNIT / 10_u64.pow(digits);
-
-    return (millis_scalar * subsec)
-        + (SUBSEC_UNIT * second)
-        + (SUBSEC_UNIT * 60_u64 * min)
-        + (SUBSEC_UNIT * 60_u64 * 60_u64 * (hour + modifier));
-}
+type CommentLine = (u32, Option<NaiveDate>, ClockTime, VarLenUnicode);
 
 // 012	10-23-2014 09:21:58	New Offset on ID CAN_ID_PRESSURE_SIG2(0x10030001): 85,09 mmHg
-fn parse_line<'a>(input: Span<'a>) -> IResult<Span<'a>, Option<CanCmt>, ErrorTree<Span<'a>>> {
-    static mut LAST_HOUR: u64 = 12;
-    static mut DID_SURPASS_MIDNIGHT: bool = false;
+fn parse_line<'a>(input: Span<'a>) -> IResult<Span<'a>, Option<CommentLine>, ErrorTree<Span<'a>>> {
     let mut tvcomment = None;
-    let parse_res = parse_comment(input);
-    if parse_res.is_ok() {
-        let (_, (id, _, _, _, (hour, min, sec), _, content)) = parse_res.unwrap();
-
-        #[allow(unused_assignments)]
-        let mut ts = 0;
-        unsafe {
-            DID_SURPASS_MIDNIGHT = (LAST_HOUR - hour) == 23;
-            if DID_SURPASS_MIDNIGHT {
-                log::debug!("Timestamps wrapped over at midnight!");
-                log::trace!("\tParsed to: {hour}:{min}:{sec}.0 > {ts}");
-                log::trace!("\tLAST_HOUR: {LAST_HOUR} hour: {hour} => {DID_SURPASS_MIDNIGHT}");
-            }
-            LAST_HOUR = hour;
-            ts = to_timestamp(hour, min, sec, 0, 0, DID_SURPASS_MIDNIGHT);
+    match parse_comment::<ErrorTree<Span>>(input) {
+        Ok((_, (id, _, date, time, _, content))) => {
+            let value = match content.parse() {
+                Ok(value) => value,
+                Err(_) => {
+                    return Err(nom::Err::Failure(ErrorTree::from_error_kind(
+                        input,
+                        ErrorKind::Verify,
+                    )))
+                }
+            };
+            tvcomment = Some((id, date, time, value));
         }
-        tvcomment = Some(CanCmt {
-            id,
-            ts,
-            value: content.parse().unwrap(),
-        });
+        // Numbers out of range make the line unreadable, other lines are no comments.
+        Err(nom::Err::Failure(e)) => return Err(nom::Err::Failure(e)),
+        Err(_) => {}
     }
 
     Ok((Span::new("".as_bytes()), tvcomment))
 }
 
-#[allow(dead_code)]
-pub fn parse_comments<P: AsRef<Path>>(comment_file: &P) -> Vec<CanCmt> {
+/// Parses a comments file.
+///
+/// With an absolute [`TimestampContext`], comments are timestamped using the date given in each line.
+///
+/// Lines which cannot be parsed are pushed to `diagnostics` or, if no diagnostics are collected, logged.
+/// Failing to read the file is an error.
+pub fn parse_comments<P: AsRef<Path>>(
+    comment_file: &P,
+    mut context: TimestampContext,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) -> Result<Vec<CanCmt>, ParserError> {
     let mut cmts = Vec::<CanCmt>::new();
-    match File::open(comment_file) {
-        Ok(file) => {
-            let mut line_buf = vec![];
-            let mut reader = BufReader::new(file);
-            while let Ok(_) = reader.read_until(b'\n', &mut line_buf) {
-                if line_buf.is_empty() {
-                    break;
-                }
-                let parse = final_parser(parse_line)(Span::new(&line_buf[..]));
-                match parse {
-                    Ok(possible_cmt) => {
-                        if let Some(can_msg) = possible_cmt {
-                            cmts.push(can_msg);
-                        }
-                    }
-                    Err(e) => {
-                        handle_error(String::from_utf8_lossy(&line_buf).to_string(), e);
-                    }
+    let mut reader = open_input(comment_file)?;
+    let mut lnr = 1;
+    let mut line_buf = vec![];
+    loop {
+        match reader.read_until(b'\n', &mut line_buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return Err(ParserError::io(comment_file, e)),
+        }
+        let parse = final_parser(parse_line)(Span::new(&line_buf[..]));
+        match parse {
+            Ok(possible_cmt) => {
+                if let Some((id, date, time, value)) = possible_cmt {
+                    // Comments carry their own date, which is more reliable than counting midnights.
+                    let ts = match date {
+                        Some(date) if context.is_absolute() => context.resolve_on(date, &time),
+                        _ => context.resolve(&time),
+                    };
+                    cmts.push(CanCmt { id, ts, value });
                 }
-                line_buf.clear();
+            }
+            Err(e) => {
+                let src = String::from_utf8_lossy(&line_buf).to_string();
+                report_error(
+                    ParserError::from_tree(comment_file, lnr, src, e),
+                    &mut diagnostics,
+                );
             }
         }
-        Err(e) => {
-            log::error!("{e:#?}");
-            exit(1);
-        }
-    };
+        line_buf.clear();
+        lnr += 1;
+    }
 
     log::debug!("Parsed {} comments.", cmts.len());
-    cmts
+    Ok(cmts)
+}
+
+/// Finds the date of the first comment in a comments file, if there is any.
+pub fn parse_comments_date<P: AsRef<Path>>(
+    comment_file: &P,
+) -> Result<Option<NaiveDate>, ParserError> {
+    let mut reader = open_input(comment_file)?;
+    let mut line_buf = vec![];
+    loop {
+        match reader.read_until(b'\n', &mut line_buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(ParserError::io(comment_file, e)),
+        }
+        let parse: Result<_, ErrorTree<Span>> = final_parser(parse_line)(Span::new(&line_buf[..]));
+        if let Ok(Some((_, Some(date), _, _))) = parse {
+            return Ok(Some(date));
+        }
+        line_buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Option<CommentLine>, ErrorTree<Span<'_>>> {
+        final_parser(parse_line)(Span::new(line.as_bytes()))
+    }
+
+    #[test]
+    fn parses_comments_with_dates() {
+        let (id, date, time, value) = parse("012\t10-23-2014 09:21:58\tNew Offset: 85,09 mmHg\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, 12);
+        assert_eq!(date, NaiveDate::from_ymd_opt(2014, 10, 23));
+        assert_eq!((time.hour, time.min, time.sec), (9, 21, 58));
+        assert_eq!(value.as_str(), "New Offset: 85,09 mmHg");
+
+        let (_, date, _, _) = parse("1 2014-10-23 09:21:58 x").unwrap().unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2014, 10, 23));
+        let (_, date, _, _) = parse("1 23.10.2014 09:21:58 x").unwrap().unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2014, 10, 23));
+        let (_, date, _, _) = parse("1 09:21:58 x").unwrap().unwrap();
+        assert_eq!(date, None);
+    }
+
+    #[test]
+    fn skips_lines_without_comment() {
+        assert!(parse("Comments of the recording\n").unwrap().is_none());
+        assert!(parse("1 no time\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_numbers_too_large_instead_of_overflowing() {
+        assert!(parse("1 10-23-123456789012345678901 09:21:58 x").is_err());
+        assert!(parse("1 123456789012345678901:21:58 x").is_err());
+    }
+
+    #[test]
+    fn reports_corrupted_lines() {
+        let path = std::env::temp_dir().join(format!("can-parser-{}.cmt", std::process::id()));
+        std::fs::write(
+            &path,
+            "1 10-23-2014 09:21:58 Start\n2 10-23-2014 99999999999999999999:00:00 Broken\n",
+        )
+        .unwrap();
+        let mut diagnostics = vec![];
+        let cmts = parse_comments(&path, TimestampContext::new(), Some(&mut diagnostics));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cmts.unwrap().len(), 1);
+        assert!(matches!(
+            &diagnostics[..],
+            [ParserError::BadLine { line_nr: 2, .. }]
+        ));
+    }
 }
@@ -1,3 +1,5 @@
+#![allow(non_local_definitions)]
+
 use hdf5::{types::VarLenUnicode, H5Type};
 
 #[derive(Debug, H5Type)]
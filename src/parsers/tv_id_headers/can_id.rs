@@ -1,31 +1,63 @@
 use std::fmt::{self, Debug};
 
-#[derive(Debug, Clone)]
+use super::{CanSignal, Multiplexing};
+use crate::parsers::CanPayload;
+
+#[derive(Debug, Clone, Default)]
 pub struct CanId {
     pub hex_id: u32,
     pub str_id: Option<String>,
     pub description: Option<String>,
     pub scale: Option<f32>,
     pub unit: Option<String>,
+    /// Signals in the payload, only known from DBC files.
+    pub signals: Vec<CanSignal>,
+    /// Number of the device, for IDs derived from another one by an ID rule.
+    pub device: Option<u32>,
+    pub device_name: Option<String>,
+    /// Name of the ID this one was derived from by an ID rule.
+    pub derived_from: Option<String>,
 }
 
 impl CanId {
     pub fn empty_with_id(hex_id: u32) -> Self {
-        let mut new = Self::default();
-        new.hex_id = hex_id;
-        new
+        Self {
+            hex_id,
+            ..Default::default()
+        }
     }
-}
 
-impl Default for CanId {
-    fn default() -> Self {
-        CanId {
-            hex_id: 0,
-            str_id: None,
-            description: None,
-            scale: None,
-            unit: None,
+    /// Decodes the value of a raw frame. IDs with a single signal take its value (NaN if the frame is too
+    /// short). Otherwise, the first four data bytes are taken as a little endian i32, multiplied by the
+    /// scale. This is how the values in the legacy logs were computed.
+    pub fn decode(&self, payload: &CanPayload) -> f32 {
+        if let [signal] = &self.signals[..] {
+            return signal
+                .decode(payload.bytes())
+                .map_or(f32::NAN, |value| value as f32);
         }
+        let mut raw = [0u8; 4];
+        for (byte, data) in raw.iter_mut().zip(payload.bytes()) {
+            *byte = *data;
+        }
+        i32::from_le_bytes(raw) as f32 * self.scale.unwrap_or(1.0)
+    }
+
+    /// Decodes every signal from the data bytes of a frame, in the order of `signals`. Signals which are
+    /// not in the frame, as they do not fit or the multiplexor selects other signals, are `None`.
+    pub fn decode_signals(&self, data: &[u8]) -> Vec<Option<f64>> {
+        let selected = self
+            .signals
+            .iter()
+            .find(|signal| signal.multiplexing == Multiplexing::Multiplexor)
+            .and_then(|multiplexor| multiplexor.raw_value(data));
+        self.signals
+            .iter()
+            .map(|signal| match signal.multiplexing {
+                Multiplexing::Multiplexed(value) if selected != Some(value) => None,
+                _ => signal.decode(data),
+            })
+            .collect()
     }
 }
 
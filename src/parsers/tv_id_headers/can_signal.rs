@@ -0,0 +1,208 @@
+/// Byte order of a signal within the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Intel, `@1` in DBC files
+    #[default]
+    LittleEndian,
+    /// Motorola, `@0` in DBC files
+    BigEndian,
+}
+
+/// Role of a signal in a multiplexed frame, where the value of the multiplexor selects which of the
+/// multiplexed signals are in the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Multiplexing {
+    /// In every frame
+    #[default]
+    None,
+    /// Selects the multiplexed signals, `M` in DBC files
+    Multiplexor,
+    /// Only in frames where the multiplexor has this value, e.g. `m3` in DBC files
+    Multiplexed(u64),
+}
+
+/// How the bits of a signal are read, as given by `SIG_VALTYPE_` in DBC files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueType {
+    /// Unsigned or two's complement integer, `0` in DBC files
+    #[default]
+    Integer,
+    /// IEEE 754 single precision float of 32 bits, `1` in DBC files
+    Float,
+    /// IEEE 754 double precision float of 64 bits, `2` in DBC files
+    Double,
+}
+
+/// A signal within the payload of a CAN frame, as defined in a DBC file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CanSignal {
+    pub name: String,
+    /// Bit the signal starts at, numbered as in DBC files: the least significant bit for little endian
+    /// signals, the most significant one for big endian signals.
+    pub start_bit: u16,
+    pub length: u16,
+    pub byte_order: ByteOrder,
+    pub signed: bool,
+    pub value_type: ValueType,
+    pub factor: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub multiplexing: Multiplexing,
+}
+
+impl CanSignal {
+    /// The bits of the signal in `data`, unscaled. `None` if the signal does not fit into the data.
+    pub fn raw_value(&self, data: &[u8]) -> Option<u64> {
+        let length = self.length as usize;
+        if length == 0 || length > 64 {
+            return None;
+        }
+        let bit = |pos: usize| {
+            data.get(pos / 8)
+                .map(|byte| ((byte >> (pos % 8)) & 1) as u64)
+        };
+
+        let start = self.start_bit as usize;
+        let mut raw = 0;
+        match self.byte_order {
+            ByteOrder::LittleEndian => {
+                for pos in (start..start + length).rev() {
+                    raw = raw << 1 | bit(pos)?;
+                }
+            }
+            ByteOrder::BigEndian => {
+                // From the most significant bit downwards, continuing with the highest bit of the next byte
+                let mut pos = start;
+                for _ in 0..length {
+                    raw = raw << 1 | bit(pos)?;
+                    pos = if pos.is_multiple_of(8) {
+                        pos + 15
+                    } else {
+                        pos - 1
+                    };
+                }
+            }
+        }
+        Some(raw)
+    }
+
+    /// Decodes the physical value of the signal from the data bytes of a frame: the raw value, taken as
+    /// two's complement if the signal is signed or as IEEE 754 float for float signals, times the
+    /// factor plus the offset. Float signals which are not 32 (or, for doubles, 64) bits long are `None`.
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        let raw = self.raw_value(data)?;
+        let value = match (self.value_type, self.length) {
+            (ValueType::Integer, _) if self.signed => {
+                let shift = 64 - self.length as u32;
+                ((raw << shift) as i64 >> shift) as f64
+            }
+            (ValueType::Integer, _) => raw as f64,
+            (ValueType::Float, 32) => f32::from_bits(raw as u32) as f64,
+            (ValueType::Double, 64) => f64::from_bits(raw),
+            _ => return None,
+        };
+        Some(value * self.factor + self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::CanId;
+
+    fn signal(start_bit: u16, length: u16, byte_order: ByteOrder, signed: bool) -> CanSignal {
+        CanSignal {
+            name: "Signal".to_string(),
+            start_bit,
+            length,
+            byte_order,
+            signed,
+            factor: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_little_endian_signals() {
+        let flow = signal(0, 16, ByteOrder::LittleEndian, false);
+        assert_eq!(flow.raw_value(&[0x34, 0x12]), Some(0x1234));
+        let nibble = signal(12, 4, ByteOrder::LittleEndian, false);
+        assert_eq!(nibble.raw_value(&[0x00, 0xA0]), Some(0xA));
+        assert_eq!(flow.raw_value(&[0x34]), None);
+    }
+
+    #[test]
+    fn decodes_big_endian_signals() {
+        let word = signal(7, 16, ByteOrder::BigEndian, false);
+        assert_eq!(word.raw_value(&[0x12, 0x34]), Some(0x1234));
+        let temp = CanSignal {
+            factor: 0.1,
+            offset: -40.0,
+            ..signal(23, 12, ByteOrder::BigEndian, true)
+        };
+        assert_eq!(temp.raw_value(&[0x00, 0x00, 0xFF, 0xE0]), Some(0xFFE));
+        let value = temp.decode(&[0x00, 0x00, 0xFF, 0xE0]).unwrap();
+        assert!((value - -40.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn decodes_signed_signals() {
+        let signed = signal(0, 8, ByteOrder::LittleEndian, true);
+        assert_eq!(signed.decode(&[0xFE]), Some(-2.0));
+        let unsigned = signal(0, 8, ByteOrder::LittleEndian, false);
+        assert_eq!(unsigned.decode(&[0xFE]), Some(254.0));
+    }
+
+    #[test]
+    fn decodes_float_signals() {
+        let float = CanSignal {
+            value_type: ValueType::Float,
+            ..signal(0, 32, ByteOrder::LittleEndian, false)
+        };
+        assert_eq!(float.decode(&1.5f32.to_le_bytes()), Some(1.5));
+        let double = CanSignal {
+            value_type: ValueType::Double,
+            factor: 2.0,
+            ..signal(0, 64, ByteOrder::LittleEndian, false)
+        };
+        assert_eq!(double.decode(&(-0.25f64).to_le_bytes()), Some(-0.5));
+        let truncated = CanSignal {
+            value_type: ValueType::Float,
+            ..signal(0, 16, ByteOrder::LittleEndian, false)
+        };
+        assert_eq!(truncated.decode(&[0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn decodes_only_the_selected_multiplexed_signals() {
+        let can_id = CanId {
+            hex_id: 0x100,
+            signals: vec![
+                CanSignal {
+                    multiplexing: Multiplexing::Multiplexor,
+                    ..signal(0, 8, ByteOrder::LittleEndian, false)
+                },
+                CanSignal {
+                    multiplexing: Multiplexing::Multiplexed(1),
+                    ..signal(8, 8, ByteOrder::LittleEndian, false)
+                },
+                CanSignal {
+                    multiplexing: Multiplexing::Multiplexed(2),
+                    ..signal(8, 16, ByteOrder::LittleEndian, false)
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            can_id.decode_signals(&[1, 0x10, 0x20]),
+            vec![Some(1.0), Some(16.0), None]
+        );
+        assert_eq!(
+            can_id.decode_signals(&[2, 0x10, 0x20]),
+            vec![Some(2.0), None, Some(8208.0)]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::{fmt, path::Path, str::FromStr};
+
+/// Extensions of compressed files, which are looked through to find the format.
+const COMPRESSED_EXTENSIONS: [&str; 3] = ["gz", "zst", "xz"];
+
+/// The formats of files describing CAN IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdsFormat {
+    /// C enum with `// description | scale unit` comments, like SmartECLA_IDs.h
+    #[default]
+    Header,
+    /// Vector DBC with messages and their signals
+    Dbc,
+}
+
+impl IdsFormat {
+    /// Guesses the format from the extension of a file, e.g. "ids.dbc" or "ids.dbc.gz".
+    pub fn from_path<P: AsRef<Path>>(path: &P) -> IdsFormat {
+        let mut path = path.as_ref().to_path_buf();
+        while path
+            .extension()
+            .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.to_string_lossy().as_ref()))
+        {
+            path.set_extension("");
+        }
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("dbc") => IdsFormat::Dbc,
+            _ => IdsFormat::Header,
+        }
+    }
+}
+
+impl fmt::Display for IdsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdsFormat::Header => write!(f, "header"),
+            IdsFormat::Dbc => write!(f, "DBC"),
+        }
+    }
+}
+
+impl FromStr for IdsFormat {
+    type Err = String;
+
+    /// Accepts "header" or "dbc".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "header" | "h" => Ok(IdsFormat::Header),
+            "dbc" => Ok(IdsFormat::Dbc),
+            _ => Err(format!(
+                "Invalid CAN IDs format {s:?}, expected header or dbc"
+            )),
+        }
+    }
+}
@@ -5,7 +5,7 @@ use nom::{
         complete::{hex_digit1, line_ending, multispace1},
         is_newline, is_space,
     },
-    error::ParseError,
+    error::{ErrorKind, ParseError},
     multi::separated_list0,
     number::complete::float,
     sequence::{delimited, preceded, tuple},
@@ -14,17 +14,16 @@ use nom::{
 
 use core::str::from_utf8;
 use nom_supreme::{error::ErrorTree, final_parser::final_parser};
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-};
+use std::{io::BufRead, path::Path};
 
-use super::common::handle_error;
-use super::common::Span;
+use super::common::{open_input, report_error, ParserError, Span};
 
 pub mod can_id;
+pub mod can_signal;
+pub mod ids_format;
 pub use can_id::CanId;
+pub use can_signal::{ByteOrder, CanSignal, Multiplexing, ValueType};
+pub use ids_format::IdsFormat;
 
 fn parse_equal<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
     delimited(multispace1, tag("="), multispace1)(input)
@@ -44,9 +43,9 @@ fn parse_to_comment_sep<'a, E: ParseError<Span<'a>>>(
     take_while(|c| !(c == b'|' || is_newline(c)))(input)
 }
 
-fn parse_comment<'a, E: ParseError<Span<'a>>>(
-    input: Span<'a>,
-) -> IResult<Span<'a>, (Option<String>, Option<f32>, Option<String>), E> {
+type IdComment = (Option<String>, Option<f32>, Option<String>);
+
+fn parse_comment<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> IResult<Span<'a>, IdComment, E> {
     let mut description: Option<String> = None;
     let mut scale: Option<f32> = None;
     let mut unit: Option<String> = None;
@@ -56,12 +55,12 @@ fn parse_comment<'a, E: ParseError<Span<'a>>>(
     // Parse content between "|" until the end of the line to a Vec
     let (r, vec) = separated_list0(tag("|"), parse_to_comment_sep)(r)?;
 
-    if vec.len() >= 1 {
+    if !vec.is_empty() {
         // Consome all spaces, we might have an empty description but still some spaces.
         let (r, _) = consume_spaces(vec[0])?;
         if !r.fragment().is_empty() {
             // The fragment is not empty. We have content.
-            description = Some(bytes_to_string::<ErrorTree<Span>>(r));
+            description = Some(bytes_to_string(r));
         }
     }
     if vec.len() >= 2 {
@@ -70,7 +69,7 @@ fn parse_comment<'a, E: ParseError<Span<'a>>>(
         if !r.fragment().is_empty() {
             // If there is, we definitly have a unit, but do we have a scale as well?
             let is_float_res = float::<Span<'a>, ErrorTree<Span>>(r);
-            if let Some((remains, scale_raw)) = is_float_res.ok() {
+            if let Ok((remains, scale_raw)) = is_float_res {
                 // We do! Extract the scale!
                 scale = Some(scale_raw);
                 // Set the remainder to what has not been parsed by "float"
@@ -82,7 +81,7 @@ fn parse_comment<'a, E: ParseError<Span<'a>>>(
             let (r, _) = consume_spaces(r)?;
             if !r.fragment().is_empty() {
                 // r then contains the unit encoded in utf-8
-                unit = Some(bytes_to_string::<ErrorTree<Span>>(r).trim().to_string());
+                unit = Some(bytes_to_string(r).trim().to_string());
             }
         }
     }
@@ -90,7 +89,7 @@ fn parse_comment<'a, E: ParseError<Span<'a>>>(
     Ok((r, (description, scale, unit)))
 }
 
-fn bytes_to_string<'a, E: ParseError<Span<'a>>>(input: Span<'a>) -> String {
+fn bytes_to_string(input: Span) -> String {
     from_utf8(input.as_ref()).unwrap().trim().to_string()
 }
 
@@ -151,9 +150,16 @@ fn parse_line<'a, E: ParseError<Span<'a>>>(line: Span<'a>) -> IResult<Span<'a>,
     // Split the comment into a list, separated by "|"s.
     let (r, (description, scale, unit)) = parse_comment(r)?;
 
-    can_id.str_id = Some(bytes_to_string::<ErrorTree<Span>>(str_id_raw));
-    can_id.hex_id =
-        u32::from_str_radix(&bytes_to_string::<ErrorTree<Span>>(hex_id_raw), 16).unwrap();
+    can_id.str_id = Some(bytes_to_string(str_id_raw));
+    can_id.hex_id = match u32::from_str_radix(&bytes_to_string(hex_id_raw), 16) {
+        Ok(hex_id) => hex_id,
+        Err(_) => {
+            return Err(nom::Err::Failure(E::from_error_kind(
+                hex_id_raw,
+                ErrorKind::HexDigit,
+            )))
+        }
+    };
     can_id.description = description;
     can_id.scale = scale;
     can_id.unit = unit;
@@ -163,33 +169,33 @@ fn parse_line<'a, E: ParseError<Span<'a>>>(line: Span<'a>) -> IResult<Span<'a>,
     Ok((r, Some(can_id)))
 }
 
-pub fn parse_canids<P: AsRef<Path>>(smartecla_file: &P) -> Vec<CanId> {
+/// Parses a SmartECLA_IDs.h file (or similar).
+///
+/// Lines which cannot be parsed are pushed to `diagnostics` or, if no diagnostics are collected, logged.
+/// Failing to read the file is an error.
+pub fn parse_canids<P: AsRef<Path>>(
+    smartecla_file: &P,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) -> Result<Vec<CanId>, ParserError> {
     let mut can_ids = Vec::<CanId>::new();
-    match File::open(smartecla_file) {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            for line_result in reader.lines() {
-                match line_result {
-                    Ok(line) => {
-                        let parse =
-                            final_parser(parse_line::<ErrorTree<Span>>)(Span::new(line.as_bytes()));
-                        match parse {
-                            Ok(possible_can_id) => {
-                                if let Some(can_id) = possible_can_id {
-                                    can_ids.push(can_id);
-                                }
-                            }
-                            Err(e) => {
-                                handle_error(line.to_owned(), e);
-                            }
-                        }
-                    }
-                    Err(_) => {}
+    let reader = open_input(smartecla_file)?;
+    for (i, line_result) in reader.lines().enumerate() {
+        let line = line_result.map_err(|e| ParserError::io(smartecla_file, e))?;
+        let parse = final_parser(parse_line::<ErrorTree<Span>>)(Span::new(line.as_bytes()));
+        match parse {
+            Ok(possible_can_id) => {
+                if let Some(can_id) = possible_can_id {
+                    can_ids.push(can_id);
                 }
             }
+            Err(e) => {
+                report_error(
+                    ParserError::from_tree(smartecla_file, i + 1, line.to_owned(), e),
+                    &mut diagnostics,
+                );
+            }
         }
-        Err(e) => log::error!("{e:#?}"),
-    };
+    }
 
-    can_ids
+    Ok(can_ids)
 }
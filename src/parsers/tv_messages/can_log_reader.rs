@@ -0,0 +1,168 @@
+use std::{io::BufRead, path::Path, vec};
+
+use super::{log_format::DETECTION_LINES, CanMsg, LogBlockReader, LogFormat, LogLine};
+use crate::parsers::common::{open_input, peek_input, InputReader, ParserError, TimestampContext};
+
+/// Size of the blocks a [`CanLogReader`] reads ahead.
+const READ_AHEAD_B: usize = 64 * 1024;
+/// Size of the sample the format of a log is detected from, unless it has enough lines before.
+const DETECTION_SAMPLE_B: usize = 64 * 1024;
+
+/// Reads CAN messages one by one from a CAN log.
+///
+/// Only a small block of the log is held in memory at a time. Lines which cannot be parsed are yielded
+/// as errors, reading continues with the next line. After an I/O error, the reader stops.
+///
+/// Every reader resolves timestamps with its own [`TimestampContext`], which starts without a date unless
+/// another one is given with [`CanLogReader::with_timestamp_context`].
+pub struct CanLogReader<R: BufRead> {
+    blocks: LogBlockReader<R>,
+    context: TimestampContext,
+    lines: vec::IntoIter<Result<LogLine, ParserError>>,
+}
+
+impl CanLogReader<InputReader> {
+    /// Opens a log with [`open_input`], detecting its format from the first lines unless `format` is given.
+    pub fn open<P: AsRef<Path>>(
+        log_file: &P,
+        format: Option<LogFormat>,
+    ) -> Result<Self, ParserError> {
+        let (reader, format) = detect_format(open_input(log_file)?, log_file, format)?;
+        Ok(Self::new(reader, log_file, format))
+    }
+}
+
+impl<R: BufRead> CanLogReader<R> {
+    /// `path` is only used to point at the source of errors.
+    pub fn new<P: AsRef<Path>>(reader: R, path: &P, format: LogFormat) -> Self {
+        log::debug!("Using {format} parser!");
+        Self {
+            blocks: LogBlockReader::new(reader, path, format, READ_AHEAD_B),
+            context: TimestampContext::new(),
+            lines: vec![].into_iter(),
+        }
+    }
+
+    pub fn with_timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Context the timestamps were resolved with so far, e.g. to continue with the next file of a recording.
+    pub fn timestamp_context(&self) -> &TimestampContext {
+        &self.context
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.blocks.format()
+    }
+
+    /// Number of lines (or BLF objects) read so far, including the block read ahead.
+    pub fn line_nr(&self) -> usize {
+        self.blocks.lines_read()
+    }
+}
+
+impl<R: BufRead> Iterator for CanLogReader<R> {
+    type Item = Result<CanMsg, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.next() {
+                return Some(line.map(|log_line| log_line.resolve(&mut self.context)));
+            }
+            let block = match self.blocks.next()? {
+                Ok(block) => block,
+                Err(e) => return Some(Err(e)),
+            };
+            self.lines = block.parse().lines.into_iter();
+        }
+    }
+}
+
+/// Detects the format of a log from its first lines, unless `format` is given, returning a reader which
+/// still starts at the beginning. Falls back to [`LogFormat::Simple`] if the format cannot be detected.
+///
+/// Pipes and decompressors may yield a few bytes at a time, so the sample is read until it holds enough
+/// lines (twice as many as looked at, for headers), [`DETECTION_SAMPLE_B`] or the whole input.
+pub(crate) fn detect_format<P: AsRef<Path>>(
+    reader: InputReader,
+    log_file: &P,
+    format: Option<LogFormat>,
+) -> Result<(InputReader, LogFormat), ParserError> {
+    let (reader, format) = match format {
+        Some(format) => (reader, format),
+        None => {
+            let (sample, reader) = peek_input(reader, log_file, |sample| {
+                sample.len() >= DETECTION_SAMPLE_B
+                    || sample.iter().filter(|c| **c == b'\n').count() >= 2 * DETECTION_LINES
+            })?;
+            let format = LogFormat::detect(&sample).unwrap_or_else(|| {
+                log::warn!(
+                    "Could not detect the format of {:#?}, assuming {}",
+                    log_file.as_ref().as_os_str(),
+                    LogFormat::default()
+                );
+                LogFormat::default()
+            });
+            (reader, format)
+        }
+    };
+    if let LogFormat::Asc(header) = format {
+        if header.relative_timestamps {
+            return Err(ParserError::Unsupported {
+                path: log_file.as_ref().to_path_buf(),
+                feature: "relative timestamps".to_string(),
+            });
+        }
+    }
+    Ok((reader, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read};
+
+    /// Yields a byte per read, like a slow pipe.
+    struct Trickle(std::io::Cursor<&'static [u8]>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn trickle(log: &'static str) -> InputReader {
+        BufReader::new(Box::new(Trickle(std::io::Cursor::new(log.as_bytes()))))
+    }
+
+    #[test]
+    fn detects_format_from_a_trickling_input() {
+        let log = "(1697539200.123456) can0 10030001#2E00000001000000\n\
+                   (1697539200.223456) can0 10030001#2F00000001000000\n";
+        let (mut reader, format) = detect_format(trickle(log), &"-", None).unwrap();
+        assert!(matches!(format, LogFormat::Candump { .. }));
+
+        let mut read_again = String::new();
+        reader.read_to_string(&mut read_again).unwrap();
+        assert_eq!(read_again, log);
+    }
+
+    #[test]
+    fn rejects_asc_logs_with_relative_timestamps() {
+        let log = "date Mon Oct 23 09:21:58.123 am 2014\n\
+                   base hex  timestamps relative\n\
+                   0.012345 1  10030001x Rx d 1 2E\n";
+        let result = detect_format(trickle(log), &"relative.asc", None);
+        assert!(matches!(
+            result,
+            Err(ParserError::Unsupported { feature, .. }) if feature == "relative timestamps"
+        ));
+
+        let absolute = log.replace("relative", "absolute").leak();
+        let (_, format) = detect_format(trickle(absolute), &"absolute.asc", None).unwrap();
+        assert!(matches!(format, LogFormat::Asc(header) if header.hex_base));
+    }
+}
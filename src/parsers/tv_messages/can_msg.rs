@@ -1,12 +1,26 @@
-use hdf5::H5Type;
 use std::fmt;
 
-#[derive(H5Type, Debug, Clone, Copy, PartialEq, PartialOrd)]
-#[repr(C)]
+/// Data length code and data bytes of a CAN frame, as far as the log contains them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CanPayload {
+    pub dlc: u8,
+    /// Only the first `dlc` bytes are meaningful, the rest is zero.
+    pub data: [u8; 8],
+}
+
+impl CanPayload {
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..(self.dlc as usize).min(8)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct CanMsg {
     pub hex_id: u32,
     pub ts: u64,
     pub value: f32,
+    /// Raw frame, only known for logs which contain the data bytes.
+    pub payload: Option<CanPayload>,
 }
 
 impl Eq for CanMsg {}
@@ -17,18 +31,21 @@ impl Ord for CanMsg {
     }
 }
 
-impl fmt::Display for CanMsg {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} @ {} = {}", self.hex_id, self.ts, self.value)
+impl PartialOrd for CanMsg {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl Default for CanMsg {
-    fn default() -> Self {
-        Self {
-            hex_id: 0,
-            value: 0.0,
-            ts: 0,
+impl fmt::Display for CanMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} @ {} = {}", self.hex_id, self.ts, self.value)?;
+        if let Some(payload) = &self.payload {
+            write!(f, " [{}]", payload.dlc)?;
+            for byte in payload.bytes() {
+                write!(f, " {byte:02X}")?;
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,170 @@
+use rayon::prelude::*;
+use std::{
+    collections::HashSet,
+    io::{BufRead, Read},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    can_log_reader::detect_format, parse_id_info, parse_log_line, LogFormat, LogIdInfo, LogLine,
+};
+use crate::parsers::common::{open_input, InputReader, ParserError};
+use crate::parsers::vector_blf::{parse_blf_objects, BlfUnpacker};
+
+/// Whole lines of a CAN log (or whole objects of a BLF log), which can be parsed independently of the
+/// rest of the file.
+///
+/// Parsing blocks yields [`LogLine`]s with unresolved times, so blocks may be parsed in parallel.
+/// To get the same timestamps as a [`super::CanLogReader`], the lines have to be resolved in order
+/// afterwards.
+#[derive(Debug, Clone)]
+pub struct LogBlock {
+    pub path: PathBuf,
+    pub format: LogFormat,
+    /// Number of the first line (or BLF object) in the block, counting from 1.
+    pub first_line_nr: usize,
+    pub data: Vec<u8>,
+}
+
+/// The result of parsing a [`LogBlock`].
+#[derive(Debug, Default)]
+pub struct ParsedBlock {
+    /// Every line with a message or an error, in order.
+    pub lines: Vec<Result<LogLine, ParserError>>,
+    /// Unit and description of every CAN ID in the block, as given by its first line of that ID.
+    pub id_infos: Vec<(u32, LogIdInfo)>,
+}
+
+impl LogBlock {
+    /// Parses every line of the block, skipping lines without messages.
+    pub fn parse(&self) -> ParsedBlock {
+        if let LogFormat::Blf(header) = self.format {
+            return ParsedBlock {
+                lines: parse_blf_objects(&self.data, &header, &self.path, self.first_line_nr),
+                id_infos: vec![],
+            };
+        }
+        let mut parsed = ParsedBlock::default();
+        let mut seen_ids = HashSet::new();
+        for (line, line_nr) in self
+            .data
+            .split_inclusive(|c| *c == b'\n')
+            .zip(self.first_line_nr..)
+        {
+            match parse_log_line(line, self.format, &self.path, line_nr) {
+                Ok(Some(log_line)) => {
+                    if seen_ids.insert(log_line.hex_id) {
+                        if let Some(info) = parse_id_info(line, self.format, &self.path, line_nr) {
+                            parsed.id_infos.push((log_line.hex_id, info));
+                        }
+                    }
+                    parsed.lines.push(Ok(log_line));
+                }
+                Ok(None) => {}
+                Err(e) => parsed.lines.push(Err(e)),
+            }
+        }
+        parsed
+    }
+}
+
+/// Parses the blocks in parallel on the current rayon thread pool, keeping their order.
+pub fn parse_blocks(blocks: &[LogBlock]) -> Vec<ParsedBlock> {
+    blocks.par_iter().map(LogBlock::parse).collect()
+}
+
+/// Reads a CAN log in [`LogBlock`]s of about `block_size` bytes, cut at line boundaries.
+///
+/// BLF logs are unpacked and cut at object boundaries instead.
+pub struct LogBlockReader<R: BufRead> {
+    reader: R,
+    path: PathBuf,
+    format: LogFormat,
+    block_size: usize,
+    lnr: usize,
+    blf: BlfUnpacker,
+    done: bool,
+}
+
+impl LogBlockReader<InputReader> {
+    /// Opens a log with [`open_input`], detecting its format from the first lines unless `format` is given.
+    pub fn open<P: AsRef<Path>>(
+        log_file: &P,
+        format: Option<LogFormat>,
+        block_size: usize,
+    ) -> Result<Self, ParserError> {
+        let (reader, format) = detect_format(open_input(log_file)?, log_file, format)?;
+        Ok(Self::new(reader, log_file, format, block_size))
+    }
+}
+
+impl<R: BufRead> LogBlockReader<R> {
+    /// `path` is only used to point at the source of errors.
+    pub fn new<P: AsRef<Path>>(reader: R, path: &P, format: LogFormat, block_size: usize) -> Self {
+        Self {
+            reader,
+            path: path.as_ref().to_path_buf(),
+            format,
+            block_size,
+            lnr: 1,
+            blf: BlfUnpacker::default(),
+            done: false,
+        }
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Number of lines (or BLF objects) in the blocks read so far.
+    pub fn lines_read(&self) -> usize {
+        self.lnr - 1
+    }
+
+    /// Reads the data of the next block along with the number of lines (or objects) in it.
+    fn read_block(&mut self) -> std::io::Result<(Vec<u8>, usize)> {
+        if let LogFormat::Blf(_) = self.format {
+            return self.blf.read_objects(&mut self.reader, self.block_size);
+        }
+        let mut data = Vec::with_capacity(self.block_size + 256);
+        (&mut self.reader)
+            .take(self.block_size as u64)
+            .read_to_end(&mut data)?;
+        // Complete the last line, so no line is split between two blocks.
+        if data.last().is_some_and(|c| *c != b'\n') {
+            self.reader.read_until(b'\n', &mut data)?;
+        }
+        let lines = data.iter().filter(|c| **c == b'\n').count();
+        Ok((data, lines))
+    }
+}
+
+impl<R: BufRead> Iterator for LogBlockReader<R> {
+    type Item = Result<LogBlock, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (data, lines) = match self.read_block() {
+            Ok(block) => block,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(ParserError::io(&self.path, e)));
+            }
+        };
+        if data.is_empty() {
+            self.done = true;
+            return None;
+        }
+
+        let first_line_nr = self.lnr;
+        self.lnr += lines;
+        Some(Ok(LogBlock {
+            path: self.path.clone(),
+            format: self.format,
+            first_line_nr,
+            data,
+        }))
+    }
+}
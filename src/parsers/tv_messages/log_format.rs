@@ -0,0 +1,115 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use std::{
+    fmt,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use super::parse_log_line;
+use crate::parsers::common::LogTime;
+use crate::parsers::peak_trc::TrcHeader;
+use crate::parsers::vector_asc::AscHeader;
+use crate::parsers::vector_blf::BlfHeader;
+
+/// Number of lines with messages looked at to detect the format of a log.
+pub(crate) const DETECTION_LINES: usize = 100;
+
+/// The formats of CAN logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `0x10FE0102 0 Prozent 08:52:25.19`
+    #[default]
+    Simple,
+    /// `100C0000h 8 2E 00 00 00 01 00 00 00 0,44921875 L/min 1 average Blood Flow 08:44:04.97`
+    Extended,
+    /// Vector ASC, along with the settings from its header
+    Asc(AscHeader),
+    /// `(1697539200.123456) can0 10030001#2E00000001000000` as written by `candump -l`, along with the
+    /// (UTC) date of its first message
+    Candump { start_date: Option<NaiveDate> },
+    /// PEAK PCAN-View trace, along with the settings from its header
+    Trc(TrcHeader),
+    /// Vector BLF, a binary format read object by object, along with the settings from its header
+    Blf(BlfHeader),
+}
+
+impl LogFormat {
+    /// Guesses the format from the first lines of a log.
+    ///
+    /// Binary BLF logs and formats with a header are recognized by it. Otherwise, every line is tried with the parsers of
+    /// the other formats, the format understanding most lines wins. Returns `None` if no parser
+    /// understands any line (or two of them the same number of lines).
+    pub fn detect(sample: &[u8]) -> Option<LogFormat> {
+        if let Some(header) = BlfHeader::parse(sample) {
+            return Some(LogFormat::Blf(header));
+        }
+        if let Some(header) = AscHeader::parse(sample) {
+            return Some(LogFormat::Asc(header));
+        }
+        if let Some(header) = TrcHeader::parse(sample) {
+            return Some(LogFormat::Trc(header));
+        }
+
+        let mut candidates = [
+            (LogFormat::Simple, 0),
+            (LogFormat::Extended, 0),
+            (LogFormat::Candump { start_date: None }, 0),
+        ];
+        let mut first_ts = None;
+        let mut lines = 0;
+        for line in sample.split(|c| *c == b'\n') {
+            if lines >= DETECTION_LINES {
+                break;
+            }
+            let mut understood = false;
+            for (format, count) in candidates.iter_mut() {
+                if let Ok(Some(log_line)) = parse_log_line(line, *format, &"", 0) {
+                    *count += 1;
+                    understood = true;
+                    if let LogTime::Unix(nanos) = log_line.time {
+                        first_ts.get_or_insert(nanos);
+                    }
+                }
+            }
+            lines += understood as usize;
+        }
+        log::trace!("Format detection: {candidates:?}");
+
+        candidates.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        match candidates {
+            [(_, 0), ..] => None,
+            [(_, best), (_, second), ..] if best == second => None,
+            [(LogFormat::Candump { .. }, _), ..] => Some(LogFormat::Candump {
+                start_date: first_ts.map(|nanos| {
+                    DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_nanos(nanos)).date_naive()
+                }),
+            }),
+            [(format, _), ..] => Some(format),
+        }
+    }
+
+    /// Date the log states it was recorded on.
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        match self {
+            LogFormat::Asc(header) => header.start_date(),
+            LogFormat::Candump { start_date } => *start_date,
+            LogFormat::Trc(header) => header.start_date(),
+            LogFormat::Blf(header) => header.start_date(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Simple => write!(f, "simple"),
+            LogFormat::Extended => write!(f, "extended"),
+            LogFormat::Asc(_) => write!(f, "ASC"),
+            LogFormat::Candump { .. } => write!(f, "candump"),
+            LogFormat::Trc(header) => {
+                write!(f, "PCAN trace {}.{}", header.version.0, header.version.1)
+            }
+            LogFormat::Blf(_) => write!(f, "BLF"),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+use super::LogFormat;
+use crate::parsers::common::{bytes_to_string, Span};
+
+/// Unit and description of a CAN ID as given in an extended log, along with where they were found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogIdInfo {
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub path: PathBuf,
+    pub line_nr: usize,
+}
+
+fn non_empty(raw: &[u8]) -> Option<String> {
+    let text = bytes_to_string(Span::new(raw));
+    (!text.is_empty()).then_some(text)
+}
+
+/// Extracts unit and description from a line of a log. Only extended logs contain them.
+///
+/// The columns of extended logs are separated by tabs. The unit follows the value in the fourth column,
+/// the description is the sixth column.
+pub fn parse_id_info<P: AsRef<Path>>(
+    line: &[u8],
+    format: LogFormat,
+    log_file: &P,
+    line_nr: usize,
+) -> Option<LogIdInfo> {
+    if format != LogFormat::Extended {
+        return None;
+    }
+    let columns: Vec<&[u8]> = line.split(|c| *c == b'\t').collect();
+    if columns.len() < 7 {
+        return None;
+    }
+    let value_column = columns[3].trim_ascii();
+    let unit = value_column
+        .iter()
+        .position(|c| *c == b' ')
+        .and_then(|space| non_empty(&value_column[space..]));
+    Some(LogIdInfo {
+        unit,
+        description: non_empty(columns[5]),
+        path: log_file.as_ref().to_path_buf(),
+        line_nr,
+    })
+}
@@ -6,73 +6,78 @@ use nom::{
         is_hex_digit, is_space,
     },
     combinator::opt,
-    error::ParseError,
+    error::{context, ContextError, ErrorKind, ParseError},
     multi::separated_list1,
-    number::complete::float,
     sequence::{preceded, tuple},
     IResult,
 };
-use nom_supreme::{error::ErrorTree, final_parser::final_parser};
-use std::str::from_utf8;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-};
+use nom_supreme::error::ErrorTree;
+use nom_supreme::final_parser::final_parser;
+use std::path::Path;
 
-use super::common::bytes_to_number;
+use super::common::{
+    bytes_to_number, decimal_to_f32, report_error, ClockTime, LogTime, ParserError,
+    TimestampContext, TIMESTAMP_CONTEXT,
+};
 
-use super::common::{bytes_to_string, handle_error, Span};
+use super::candump::parse_candump;
+use super::common::{bytes_to_string, Span};
+use super::peak_trc::parse_trc;
+use super::vector_asc::parse_asc;
 
+pub mod can_log_reader;
 pub mod can_msg;
-pub use can_msg::CanMsg;
-
-pub type FnCanMsgParser<'a> =
-    fn(Span<'a>) -> IResult<Span<'a>, Option<CanMsg>, ErrorTree<Span<'a>>>;
-
-fn to_timestamp(
-    hour: u64,
-    min: u64,
-    second: u64,
-    subsec: u64,
-    digits: u32,
-    did_surpass_midnight: bool,
-) -> u64 {
-    // Measure all in nanoseconds
-    static SUBSEC_UNIT: u64 = 1_000_000_000;
-    // Hour modifier, if we surpassed midnight.
-    let modifier: u64 = if did_surpass_midnight { 24 } else { 0 };
-    // Mostly, subsecond units are not given with the righ amount of
-    // digits and are therefore parsed wrong. Correct for that here.
-    let millis_scalar: u64 = SUBSEC_UNIT / 10_u64.pow(digits);
-
-    return (millis_scalar * subsec)
-        + (SUBSEC_UNIT * second)
-        + (SUBSEC_UNIT * 60_u64 * min)
-        + (SUBSEC_UNIT * 60_u64 * 60_u64 * (hour + modifier));
+pub mod log_block;
+pub mod log_format;
+pub mod log_id_info;
+pub use can_log_reader::CanLogReader;
+pub use can_msg::{CanMsg, CanPayload};
+pub use log_block::{parse_blocks, LogBlock, LogBlockReader, ParsedBlock};
+pub use log_format::LogFormat;
+pub use log_id_info::{parse_id_info, LogIdInfo};
+
+/// A message as found in a log line, with its time not yet resolved to a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogLine {
+    pub hex_id: u32,
+    pub value: f32,
+    pub time: LogTime,
+    pub payload: Option<CanPayload>,
 }
 
-fn parse_ts<'a, E: ParseError<Span<'a>>>(raw_ts: Span<'a>) -> IResult<Span<'a>, u64, E> {
-    static mut LAST_HOUR: u64 = 12;
-    static mut DID_SURPASS_MIDNIGHT: bool = false;
+impl LogLine {
+    /// Turns the line into a message, resolving its time with the context of the file it is from.
+    pub fn resolve(self, context: &mut TimestampContext) -> CanMsg {
+        CanMsg {
+            hex_id: self.hex_id,
+            value: self.value,
+            ts: context.resolve_time(&self.time),
+            payload: self.payload,
+        }
+    }
+}
 
+fn parse_ts<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
+    raw_ts: Span<'a>,
+) -> IResult<Span<'a>, ClockTime, E> {
     // Timestamps are fucked.
     // Due to the misfortunate format of timestamps throughout all log files, we have to do something
-    // less straight forward than parsing from "normal" time.
-    // Logs might surpass midnight, which may or may not be inidcated by a preceeding "1." before the
-    // current time. Sometimes, a leading "0." indicates that we did not surpass midnight yet.
-    // If midnight is surpassed, "0." may or may not change to "1.".
+    // less straight forward than parsing from "normal" time. Here, we only take the time apart.
+    // Resolving it to a timestamp is up to the TimestampContext of the file.
     //
     // [0.|1.]HH:MM:SS.[0-9]{1..4}
-    let (r, (day_indicator, hour_raw, _, min_raw, _, sec_raw, millis)) = tuple((
-        opt(tuple((digit1, tag(".")))),
-        digit1,
-        tag(":"),
-        digit1,
-        tag(":"),
-        digit1,
-        opt(tuple((tag("."), digit1))),
-    ))(raw_ts)?;
+    let (r, (day_indicator, hour_raw, _, min_raw, _, sec_raw, millis)) = context(
+        TIMESTAMP_CONTEXT,
+        tuple((
+            opt(tuple((digit1, tag(".")))),
+            digit1,
+            tag(":"),
+            digit1,
+            tag(":"),
+            digit1,
+            opt(tuple((tag("."), digit1))),
+        )),
+    )(raw_ts)?;
 
     let (hour, _) = bytes_to_number(hour_raw);
     let (min, _) = bytes_to_number(min_raw);
@@ -84,40 +89,19 @@ fn parse_ts<'a, E: ParseError<Span<'a>>>(raw_ts: Span<'a>) -> IResult<Span<'a>,
         (0, 0)
     };
 
-    #[allow(unused_assignments)]
-    let mut ts = 0;
-    unsafe {
-        let midnight_indicator = match day_indicator {
-            // Parse "digit" from a byte to a string and check whether it is greater 0.
-            // The digit is encoded in UTF-8, which enumerates digits starting with "30" for 0.
-            Some((digit, _)) => {
-                let (digit, _) = bytes_to_number(digit);
-                digit > 0
-            }
-            None => false,
-        };
-        let hour_diff = (LAST_HOUR - hour) == 23;
-        DID_SURPASS_MIDNIGHT = midnight_indicator || hour_diff;
-        if DID_SURPASS_MIDNIGHT {
-            log::debug!("Timestamps wrapped over at midnight!");
-            log::trace!("{}", bytes_to_string(raw_ts));
-            log::trace!(
-                "\tParsed to: {}.{hour}:{min}:{sec}.{subsec} > {ts}",
-                bytes_to_string(
-                    day_indicator
-                        .unwrap_or((Span::new("0".as_bytes()), Span::new(".".as_bytes())))
-                        .0
-                )
-            );
-            log::trace!(
-                "\tLAST_HOUR: {LAST_HOUR}, hour_diff: {hour_diff} => {DID_SURPASS_MIDNIGHT}"
-            );
-        }
-        LAST_HOUR = hour;
-        ts = to_timestamp(hour, min, sec, subsec, digits, DID_SURPASS_MIDNIGHT);
-    }
-
-    Ok((r, ts))
+    let day = day_indicator.map(|(digit, _)| bytes_to_number(digit).0);
+
+    Ok((
+        r,
+        ClockTime {
+            day,
+            hour,
+            min,
+            sec,
+            subsec,
+            digits,
+        },
+    ))
 }
 
 fn parse_spaces<'a>(line: Span<'a>) -> IResult<Span<'a>, Span<'a>, ErrorTree<Span<'a>>> {
@@ -125,10 +109,10 @@ fn parse_spaces<'a>(line: Span<'a>) -> IResult<Span<'a>, Span<'a>, ErrorTree<Spa
 }
 
 //100C0000h	8	2E 00 00 00 01 00 00 00 	0,44921875 L/min	1	 average Blood Flow 		08:44:04.97
-fn parse_extended<'a, E: ParseError<Span<'a>>>(
+fn parse_extended<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
     line: Span<'a>,
-) -> IResult<Span<'a>, Option<CanMsg>, E> {
-    let mut can_msg: Option<CanMsg> = None;
+) -> IResult<Span<'a>, Option<LogLine>, E> {
+    let mut log_line: Option<LogLine> = None;
 
     if line.len() > 0 {
         let parse_res = separated_list1(parse_spaces, take_while(|c| !is_space(c)))(line);
@@ -138,15 +122,10 @@ fn parse_extended<'a, E: ParseError<Span<'a>>>(
 
         let (_, mut list) = parse_res.unwrap();
 
-        if list.len() <= 10 {
+        list.retain(|item| !item.is_empty());
+        if list.len() < 4 {
             // This is not a "real" CAN log message. It might be a comment. Just ignore those
-            return Ok((Span::new("".as_bytes()), can_msg));
-        }
-
-        for i in 0..(list.len() - 1) {
-            if bytes_to_string(*list.get(i).unwrap()) == String::from("") {
-                list.remove(i);
-            }
+            return Ok((Span::new("".as_bytes()), log_line));
         }
 
         // CanId is always the first item. If its not, this is not a valid line
@@ -156,24 +135,57 @@ fn parse_extended<'a, E: ParseError<Span<'a>>>(
         let hex_id_res = u32::from_str_radix(bytes_to_string(hex_id_raw).as_str(), 16);
 
         if hex_id_res.is_err() {
-            return Ok((Span::new("".as_bytes()), can_msg));
+            return Ok((Span::new("".as_bytes()), log_line));
         }
         let hex_id = hex_id_res.unwrap();
-        let (_, value) = float(list[10])?;
 
-        let (_, ts) = parse_ts(list[list.len() - 1])?;
+        // The value follows the DLC data bytes. Lines with a DLC which cannot be read are taken as
+        // having eight data bytes, without keeping them.
+        let dlc = bytes_to_string(list[1])
+            .parse::<u8>()
+            .ok()
+            .filter(|dlc| *dlc <= 8);
+        let value_index = 2 + dlc.unwrap_or(8) as usize;
+        if list.len() <= value_index + 1 {
+            return Ok((Span::new("".as_bytes()), log_line));
+        }
+        let payload = dlc.and_then(|dlc| {
+            let mut data = [0u8; 8];
+            for (byte, raw) in data.iter_mut().zip(&list[2..value_index]) {
+                *byte = u8::from_str_radix(bytes_to_string(*raw).as_str(), 16).ok()?;
+            }
+            Some(CanPayload { dlc, data })
+        });
+
+        // Values use a comma as decimal separator, which "float" would stop at.
+        let value = match decimal_to_f32(list[value_index]) {
+            Some(value) => value,
+            None => {
+                return Err(nom::Err::Failure(E::from_error_kind(
+                    list[value_index],
+                    ErrorKind::Float,
+                )))
+            }
+        };
 
-        can_msg = Some(CanMsg { hex_id, value, ts });
+        let (_, time) = parse_ts(list[list.len() - 1])?;
+
+        log_line = Some(LogLine {
+            hex_id,
+            value,
+            time: LogTime::Clock(time),
+            payload,
+        });
     }
 
-    Ok((Span::new("".as_bytes()), can_msg))
+    Ok((Span::new("".as_bytes()), log_line))
 }
 
 // 0x10FE0102	0 Prozent	08:52:25.19
-fn parse_simple<'a, E: ParseError<Span<'a>>>(
+fn parse_simple<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
     line: Span<'a>,
-) -> IResult<Span<'a>, Option<CanMsg>, E> {
-    let mut can_msg: Option<CanMsg> = None;
+) -> IResult<Span<'a>, Option<LogLine>, E> {
+    let mut log_line: Option<LogLine> = None;
 
     if line.len() > 0 {
         let parse_res = separated_list1(parse_spaces, take_while(|c| !is_space(c)))(line);
@@ -193,65 +205,142 @@ fn parse_simple<'a, E: ParseError<Span<'a>>>(
         let hex_id_res =
             tuple((tag::<_, _, ErrorTree<Span>>("0x"), take_while(is_hex_digit)))(list[0]);
         if hex_id_res.is_err() {
-            return Ok((Span::new("".as_bytes()), can_msg));
+            return Ok((Span::new("".as_bytes()), log_line));
         }
         let (_, (_, hex_id_raw)) = hex_id_res.unwrap();
-        let hex_id_res = u32::from_str_radix(from_utf8(hex_id_raw.as_ref()).unwrap().trim(), 16);
-
-        let hex_id = hex_id_res.unwrap();
-        let value: f32 = bytes_to_string(list[1])
-            .trim()
-            .replace(',', ".")
-            .parse()
-            .unwrap();
-        let (_, ts) = parse_ts(list[list.len() - 1])?;
-
-        can_msg = Some(CanMsg { hex_id, value, ts });
+        let hex_id = match u32::from_str_radix(bytes_to_string(hex_id_raw).as_str(), 16) {
+            Ok(hex_id) => hex_id,
+            Err(_) => {
+                return Err(nom::Err::Failure(E::from_error_kind(
+                    list[0],
+                    ErrorKind::HexDigit,
+                )))
+            }
+        };
+        let value = match decimal_to_f32(list[1]) {
+            Some(value) => value,
+            None => {
+                return Err(nom::Err::Failure(E::from_error_kind(
+                    list[1],
+                    ErrorKind::Float,
+                )))
+            }
+        };
+        let (_, time) = parse_ts(list[list.len() - 1])?;
+
+        log_line = Some(LogLine {
+            hex_id,
+            value,
+            time: LogTime::Clock(time),
+            payload: None,
+        });
     }
 
-    Ok((Span::new("".as_bytes()), can_msg))
+    Ok((Span::new("".as_bytes()), log_line))
 }
 
-pub fn parse_messages<'a, P: AsRef<Path>>(log_file: &P, is_extended: bool) -> Vec<CanMsg> {
-    if is_extended {
-        log::debug!("Using extended parser!");
-    }
-    let mut lnr = 1;
-    let mut can_msgs = Vec::<CanMsg>::new();
-    let mut line_buf = vec![];
-    match File::open(log_file) {
-        Ok(file) => {
-            let mut reader = BufReader::new(file);
-            while let Ok(_) = reader.read_until(b'\n', &mut line_buf) {
-                if line_buf.is_empty() {
-                    break;
-                }
-                let parser: FnCanMsgParser = if is_extended {
-                    parse_extended
-                } else {
-                    parse_simple
-                };
-                let parse = final_parser(parser)(Span::new(&line_buf[..]));
-                match parse {
-                    Ok(possible_can_msg) => {
-                        if let Some(can_msg) = possible_can_msg {
-                            can_msgs.push(can_msg);
-                        }
-                    }
-                    Err(e) => {
-                        handle_error(String::from_utf8_lossy(&line_buf).to_string(), e);
-                    }
-                }
-                line_buf.clear();
-
-                if lnr % 5000000 == 0 {
-                    log::trace!("Still at it...")
-                }
-                lnr += 1
-            }
+/// Parses a single line of a CAN log, `Ok(None)` if it holds no message.
+///
+/// Binary BLF logs have no lines, they are parsed in [`LogBlock`]s.
+///
+/// `log_file` and `line_nr` are only used to point at the source of errors.
+pub fn parse_log_line<P: AsRef<Path>>(
+    line: &[u8],
+    format: LogFormat,
+    log_file: &P,
+    line_nr: usize,
+) -> Result<Option<LogLine>, ParserError> {
+    let line_span = Span::new(line);
+    let parsed: Result<Option<LogLine>, ErrorTree<Span>> = match format {
+        LogFormat::Simple => final_parser(parse_simple::<ErrorTree<Span>>)(line_span),
+        LogFormat::Extended => final_parser(parse_extended::<ErrorTree<Span>>)(line_span),
+        LogFormat::Candump { .. } => final_parser(parse_candump::<ErrorTree<Span>>)(line_span),
+        LogFormat::Trc(header) => {
+            final_parser(|line| parse_trc::<ErrorTree<Span>>(line, &header))(line_span)
+        }
+        LogFormat::Asc(header) => {
+            final_parser(|line| parse_asc::<ErrorTree<Span>>(line, &header))(line_span)
         }
-        Err(e) => println!("{e:#?}"),
+        LogFormat::Blf(_) => return Ok(None),
     };
+    parsed.map_err(|e| {
+        let src = String::from_utf8_lossy(line).to_string();
+        ParserError::from_tree(log_file, line_nr, src, e)
+    })
+}
+
+/// Parses a whole CAN log file, detecting its format unless `format` is given.
+///
+/// Lines which cannot be parsed are pushed to `diagnostics` or, if no diagnostics are collected, logged.
+/// Failing to read the file is an error. To avoid holding all messages in memory, use [`CanLogReader`].
+pub fn parse_messages<P: AsRef<Path>>(
+    log_file: &P,
+    format: Option<LogFormat>,
+    context: TimestampContext,
+    mut diagnostics: Option<&mut Vec<ParserError>>,
+) -> Result<Vec<CanMsg>, ParserError> {
+    let mut can_msgs = Vec::<CanMsg>::new();
+    let reader = CanLogReader::open(log_file, format)?.with_timestamp_context(context);
+    for result in reader {
+        match result {
+            Ok(can_msg) => can_msgs.push(can_msg),
+            Err(e @ ParserError::Io { .. }) => return Err(e),
+            Err(e) => report_error(e, &mut diagnostics),
+        }
+    }
 
-    can_msgs
+    Ok(can_msgs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_extended_line(line: &str) -> LogLine {
+        parse_log_line(line.as_bytes(), LogFormat::Extended, &"", 1)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_extended_lines_with_eight_data_bytes() {
+        let line = parse_extended_line(
+            "100C0000h\t8\t2E 00 00 00 01 00 00 00 \t0,44921875 L/min\t1\t average Blood Flow \t\t08:44:04.97",
+        );
+        assert_eq!(line.hex_id, 0x100C0000);
+        assert_eq!(line.value, 0.44921875);
+        let payload = line.payload.unwrap();
+        assert_eq!(payload.dlc, 8);
+        assert_eq!(payload.data, [0x2E, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn takes_the_value_after_the_dlc_data_bytes() {
+        let line = parse_extended_line(
+            "10030001h\t2\t10 27 \t100,00 mmHg\t1\t Pressure signal 2 \t\t08:44:05.01",
+        );
+        assert_eq!(line.value, 100.0);
+        let payload = line.payload.unwrap();
+        assert_eq!(payload.dlc, 2);
+        assert_eq!(payload.bytes(), [0x10, 0x27]);
+        assert!(matches!(
+            line.time,
+            LogTime::Clock(ClockTime {
+                hour: 8,
+                min: 44,
+                sec: 5,
+                subsec: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn keeps_lines_with_an_unreadable_dlc_without_payload() {
+        let line = parse_extended_line(
+            "100C0000h\tx\t2E 00 00 00 01 00 00 00 \t0,5 L/min\t1\t average Blood Flow \t\t08:44:04.97",
+        );
+        assert_eq!(line.value, 0.5);
+        assert!(line.payload.is_none());
+    }
 }
@@ -0,0 +1,116 @@
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Number of lines at the start of a log searched for an ASC header.
+const HEADER_LINES: usize = 20;
+
+/// Settings from the header of a Vector ASC log.
+///
+/// ```text
+/// date Mon Oct 23 09:21:58.123 am 2014
+/// base hex  timestamps absolute
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AscHeader {
+    /// Start of the measurement, which the timestamps of the messages are relative to.
+    pub start: Option<NaiveDateTime>,
+    /// Whether IDs and data bytes are given in hex or decimal.
+    pub hex_base: bool,
+    /// Timestamps relative to the previous event instead of the start of the measurement.
+    pub relative_timestamps: bool,
+}
+
+impl Default for AscHeader {
+    fn default() -> Self {
+        Self {
+            start: None,
+            hex_base: true,
+            relative_timestamps: false,
+        }
+    }
+}
+
+fn parse_date(raw: &str) -> Option<NaiveDateTime> {
+    const FORMATS: [&str; 2] = ["%b %d %I:%M:%S%.f %p %Y", "%b %d %H:%M:%S%.f %Y"];
+    // The weekday is skipped, repeated spaces (as in "Mon Oct  2") are ignored.
+    let raw = raw.split_whitespace().skip(1).collect::<Vec<_>>().join(" ");
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&raw, format).ok())
+}
+
+impl AscHeader {
+    /// Reads the header from the beginning of a log, `None` if it is not an ASC log.
+    pub fn parse(sample: &[u8]) -> Option<AscHeader> {
+        let mut header = AscHeader::default();
+        let mut is_asc = false;
+        let text = String::from_utf8_lossy(sample);
+        for line in text.lines().take(HEADER_LINES) {
+            let line = line.trim();
+            if let Some(date) = line.strip_prefix("date ") {
+                is_asc = true;
+                header.start = parse_date(date);
+                if header.start.is_none() {
+                    log::warn!("Could not read the start date of the ASC log: {date:?}");
+                }
+            } else if line.starts_with("base ") {
+                is_asc = true;
+                let words: Vec<&str> = line.split_whitespace().collect();
+                header.hex_base = !words.contains(&"dec");
+                header.relative_timestamps = words.contains(&"relative");
+            } else if line.starts_with("Begin Triggerblock") {
+                is_asc = true;
+            }
+        }
+        is_asc.then_some(header)
+    }
+
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        self.start.map(|start| start.date())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_date_and_base_timestamps() {
+        let header = AscHeader::parse(
+            b"date Mon Oct 23 09:21:58.123 am 2014\nbase hex  timestamps absolute\n",
+        )
+        .unwrap();
+        let start = NaiveDate::from_ymd_opt(2014, 10, 23)
+            .unwrap()
+            .and_hms_milli_opt(9, 21, 58, 123)
+            .unwrap();
+        assert_eq!(header.start, Some(start));
+        assert_eq!(header.start_date(), NaiveDate::from_ymd_opt(2014, 10, 23));
+        assert!(header.hex_base);
+        assert!(!header.relative_timestamps);
+    }
+
+    #[test]
+    fn parses_24_hour_dates_and_decimal_relative_bases() {
+        let header =
+            AscHeader::parse(b"date Mon Oct  2 14:05:00 2023\nbase dec timestamps relative\n")
+                .unwrap();
+        assert_eq!(
+            header.start,
+            NaiveDate::from_ymd_opt(2023, 10, 2)
+                .unwrap()
+                .and_hms_opt(14, 5, 0)
+        );
+        assert!(!header.hex_base);
+        assert!(header.relative_timestamps);
+    }
+
+    #[test]
+    fn ignores_logs_without_header() {
+        assert_eq!(
+            AscHeader::parse(b"0x10FE0102 0 Prozent 08:52:25.19\n"),
+            None
+        );
+        let header = AscHeader::parse(b"date unreadable\n").unwrap();
+        assert_eq!(header.start, None);
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::Timelike;
+use nom::{
+    bytes::complete::take_while,
+    character::{complete::space1, is_digit, is_space},
+    error::{ContextError, ErrorKind, ParseError},
+    multi::separated_list1,
+    IResult,
+};
+
+use super::common::{bytes_to_string, ClockTime, LogTime, Span};
+use super::tv_messages::{CanPayload, LogLine};
+
+pub mod asc_header;
+pub use asc_header::AscHeader;
+
+fn parse_hex_or_dec(raw: &str, hex_base: bool) -> Option<u32> {
+    if hex_base {
+        u32::from_str_radix(raw, 16).ok()
+    } else {
+        raw.parse().ok()
+    }
+}
+
+/// Seconds since the start of the measurement, e.g. "12.345678".
+fn parse_offset(raw: Span) -> Option<u64> {
+    let text = bytes_to_string(raw);
+    let (secs, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    if secs.is_empty() || !secs.bytes().all(is_digit) || !fraction.bytes().all(is_digit) {
+        return None;
+    }
+    let fraction = &fraction[..fraction.len().min(9)];
+    let subsec: u64 = format!("{fraction:0<9}").parse().ok()?;
+    Some(secs.parse::<u64>().ok()? * 1_000_000_000 + subsec)
+}
+
+fn start_of_measurement(header: &AscHeader) -> u64 {
+    match header.start {
+        Some(start) => {
+            start.num_seconds_from_midnight() as u64 * 1_000_000_000 + start.nanosecond() as u64
+        }
+        None => 0,
+    }
+}
+
+// 0.012345 1  10030001x       Rx   d 8 2E 00 00 00 01 00 00 00  Length = 0 BitCount = 0 ID = 268632065x
+// 0.020000 CANFD   1 Rx   10030001x  Name  1 0 8  8 2E 00 00 00 01 00 00 00   0 0 0 0 0 0
+/// Parses a line of a Vector ASC log. Only data frames yield messages, all other events are skipped.
+///
+/// The value of the messages is NaN, as it has to be decoded from the payload with the CAN ID's scale.
+pub fn parse_asc<'a, E: ParseError<Span<'a>> + ContextError<Span<'a>>>(
+    line: Span<'a>,
+    header: &AscHeader,
+) -> IResult<Span<'a>, Option<LogLine>, E> {
+    let done = Span::new("".as_bytes());
+    let (_, list) = separated_list1(space1, take_while(|c| !is_space(c)))(line)?;
+    let list: Vec<Span> = list
+        .into_iter()
+        .filter(|token| !token.trim_ascii().is_empty())
+        .collect();
+
+    let Some(offset) = list.first().and_then(|raw| parse_offset(*raw)) else {
+        // Header lines, "Begin Triggerblock" and the like
+        return Ok((done, None));
+    };
+    if list.len() < 6 {
+        return Ok((done, None));
+    }
+
+    let is_fd = bytes_to_string(list[1]) == "CANFD";
+    let (id_idx, len_idx) = if is_fd {
+        // A symbolic name may follow the ID, then come BRS, ESI, DLC and the data length.
+        let name_offset = match bytes_to_string(list[5]).as_str() {
+            "0" | "1" => 0,
+            _ => 1,
+        };
+        (4, 8 + name_offset)
+    } else {
+        if bytes_to_string(list[4]) != "d" {
+            // Remote frames, error frames, statistics, ...
+            return Ok((done, None));
+        }
+        (2, 5)
+    };
+    if list.len() <= len_idx {
+        return Ok((done, None));
+    }
+
+    let id_raw = bytes_to_string(list[id_idx]);
+    let Some(hex_id) = parse_hex_or_dec(id_raw.trim_end_matches('x'), header.hex_base) else {
+        return Ok((done, None));
+    };
+
+    // The data length of CAN FD frames is always decimal, the DLC of classic frames follows the base.
+    let len_raw = bytes_to_string(list[len_idx]);
+    let len = if is_fd {
+        len_raw.parse::<usize>().ok()
+    } else {
+        parse_hex_or_dec(&len_raw, header.hex_base).map(|dlc| dlc as usize)
+    };
+    let len = match len {
+        Some(len) if list.len() > len_idx + len => len,
+        _ => {
+            return Err(nom::Err::Failure(E::from_error_kind(
+                list[len_idx],
+                ErrorKind::Digit,
+            )))
+        }
+    };
+
+    // Only the first 8 bytes of CAN FD frames are kept.
+    let mut data = [0u8; 8];
+    for (byte, raw) in data.iter_mut().zip(&list[len_idx + 1..len_idx + 1 + len]) {
+        *byte = match parse_hex_or_dec(&bytes_to_string(*raw), header.hex_base) {
+            Some(byte) if byte <= 0xFF => byte as u8,
+            _ => {
+                return Err(nom::Err::Failure(E::from_error_kind(
+                    *raw,
+                    ErrorKind::HexDigit,
+                )))
+            }
+        };
+    }
+
+    Ok((
+        done,
+        Some(LogLine {
+            hex_id,
+            value: f32::NAN,
+            time: LogTime::Clock(ClockTime::from_nanos(start_of_measurement(header) + offset)),
+            payload: Some(CanPayload {
+                dlc: len.min(8) as u8,
+                data,
+            }),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+
+    fn parse(line: &str, header: &AscHeader) -> Option<LogLine> {
+        parse_asc::<VerboseError<Span>>(Span::new(line.as_bytes()), header)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn parses_classic_data_frames() {
+        let header = AscHeader::default();
+        let line = parse(
+            "   0.012345 1  10030001x       Rx   d 8 2E 00 00 00 01 00 00 00  Length = 0 BitCount = 0 ID = 268632065x",
+            &header,
+        )
+        .unwrap();
+        assert_eq!(line.hex_id, 0x10030001);
+        assert_eq!(line.time, LogTime::Clock(ClockTime::from_nanos(12_345_000)));
+        assert_eq!(
+            line.payload,
+            Some(CanPayload {
+                dlc: 8,
+                data: [0x2E, 0, 0, 0, 1, 0, 0, 0]
+            })
+        );
+        assert!(line.value.is_nan());
+
+        assert_eq!(
+            parse("   0.020000 1  10030001x       Rx   r", &header),
+            None
+        );
+        assert_eq!(
+            parse(
+                "Begin Triggerblock Mon Oct 23 09:21:58.123 am 2014",
+                &header
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_can_fd_frames_relative_to_the_start() {
+        let header = AscHeader::parse(
+            b"date Mon Oct 23 09:21:58.000 am 2014\nbase hex timestamps absolute\n",
+        )
+        .unwrap();
+        let line = parse(
+            "   1.500000 CANFD   1 Rx   10030001x  Name  1 0 a 12 01 02 03 04 05 06 07 08 09 0A 0B 0C   0 0 0 0 0 0",
+            &header,
+        )
+        .unwrap();
+        assert_eq!(line.hex_id, 0x10030001);
+        let start = (9 * 3600 + 21 * 60 + 58) * 1_000_000_000;
+        assert_eq!(
+            line.time,
+            LogTime::Clock(ClockTime::from_nanos(start + 1_500_000_000))
+        );
+        assert_eq!(
+            line.payload,
+            Some(CanPayload {
+                dlc: 8,
+                data: [1, 2, 3, 4, 5, 6, 7, 8]
+            })
+        );
+    }
+
+    #[test]
+    fn parses_decimal_bases() {
+        let header = AscHeader {
+            hex_base: false,
+            ..Default::default()
+        };
+        let line = parse("0.5 1 256 Rx d 2 46 255", &header).unwrap();
+        assert_eq!(line.hex_id, 256);
+        assert_eq!(line.payload.unwrap().data[..2], [46, 255]);
+    }
+
+    #[test]
+    fn rejects_frames_with_missing_data_bytes() {
+        let line = "0.5 1 100 Rx d 8 01 02";
+        assert!(
+            parse_asc::<VerboseError<Span>>(Span::new(line.as_bytes()), &AscHeader::default())
+                .is_err()
+        );
+    }
+}
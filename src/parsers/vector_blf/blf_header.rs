@@ -0,0 +1,65 @@
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Signature at the start of every BLF file.
+pub const FILE_SIGNATURE: &[u8; 4] = b"LOGG";
+
+/// Size of the file header up to the end of the start and stop times.
+const MIN_HEADER_SIZE: usize = 72;
+const START_TIME_OFFSET: usize = 40;
+
+/// Settings from the file header of a Vector BLF log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlfHeader {
+    /// Start of the measurement, which the timestamps of the objects are relative to.
+    pub start: Option<NaiveDateTime>,
+    /// Size of the file header, the first object follows right after it.
+    pub header_size: u32,
+    /// Number of objects the file states it contains.
+    pub object_count: u32,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// A Windows `SYSTEMTIME`: year, month, weekday, day, hour, minute, second and millisecond.
+fn parse_system_time(data: &[u8]) -> Option<NaiveDateTime> {
+    let field = |nr: usize| u16_at(data, 2 * nr) as u32;
+    NaiveDate::from_ymd_opt(field(0) as i32, field(1), field(3))?.and_hms_milli_opt(
+        field(4),
+        field(5),
+        field(6),
+        field(7),
+    )
+}
+
+impl BlfHeader {
+    /// Reads the header from the beginning of a log, `None` if it is not a BLF log.
+    pub fn parse(sample: &[u8]) -> Option<BlfHeader> {
+        if sample.len() < MIN_HEADER_SIZE || !sample.starts_with(FILE_SIGNATURE) {
+            return None;
+        }
+        let header_size = u32_at(sample, 4);
+        if (header_size as usize) < MIN_HEADER_SIZE {
+            return None;
+        }
+        let start = parse_system_time(&sample[START_TIME_OFFSET..START_TIME_OFFSET + 16]);
+        if start.is_none() {
+            log::warn!("Could not read the start time of the BLF log");
+        }
+        Some(BlfHeader {
+            start,
+            header_size,
+            object_count: u32_at(sample, 32),
+        })
+    }
+
+    /// Date the measurement started on.
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        self.start.map(|start| start.date())
+    }
+}
@@ -0,0 +1,532 @@
+use chrono::Timelike;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::common::{ClockTime, LogTime, ParserError};
+use super::tv_messages::{CanPayload, LogLine};
+
+pub mod blf_header;
+pub use blf_header::BlfHeader;
+
+/// Signature at the start of every object.
+const OBJECT_SIGNATURE: &[u8; 4] = b"LOBJ";
+/// Size of the part of the object header which every object starts with.
+const BASE_HEADER_SIZE: usize = 16;
+
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+/// Object timestamps count 10 µs steps with this flag, nanoseconds otherwise.
+const TIME_TEN_MICS: u32 = 0x1;
+const REMOTE_FLAG: u8 = 0x80;
+const REMOTE_FLAG_64: u32 = 0x10;
+const EXTENDED_ID_FLAG: u32 = 0x8000_0000;
+
+/// Number of data bytes of CAN FD frames by their DLC.
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Size and type of the object at the start of `data`, if its base header is complete.
+fn object_size_and_type(data: &[u8]) -> Option<(usize, u32)> {
+    Some((u32_at(data, 8)? as usize, u32_at(data, 12)?))
+}
+
+/// Distance from the start of an object to the start of the next one. Objects are padded, except
+/// for CAN FD messages with 64 bytes.
+fn object_stride(size: usize, object_type: u32) -> usize {
+    match object_type {
+        CAN_FD_MESSAGE_64 => size,
+        _ => size + size % 4,
+    }
+}
+
+/// Offset of the next object signature in `data`, searching from `from`.
+fn find_signature(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(OBJECT_SIGNATURE.len())
+        .position(|window| window == OBJECT_SIGNATURE)
+        .map(|pos| from + pos)
+}
+
+/// Reads the objects of a BLF file, unpacking the log containers they are usually stored in.
+///
+/// Objects may be split between two containers, so the unpacked data is cut at object boundaries.
+#[derive(Debug, Default)]
+pub(crate) struct BlfUnpacker {
+    /// Unpacked objects which were not handed out yet.
+    pending: Vec<u8>,
+    header_skipped: bool,
+    done: bool,
+}
+
+impl BlfUnpacker {
+    /// Reads whole objects until there are at least `min_size` bytes of them or the file ends, and
+    /// returns them along with their number.
+    pub fn read_objects<R: Read>(
+        &mut self,
+        reader: &mut R,
+        min_size: usize,
+    ) -> io::Result<(Vec<u8>, usize)> {
+        if !self.header_skipped {
+            let mut start = [0u8; 8];
+            reader.read_exact(&mut start)?;
+            let header_size = u32::from_le_bytes(start[4..].try_into().unwrap()) as u64;
+            io::copy(
+                &mut reader.by_ref().take(header_size.saturating_sub(8)),
+                &mut io::sink(),
+            )?;
+            self.header_skipped = true;
+        }
+
+        loop {
+            let (end, count) = self.complete_objects();
+            if end >= min_size || self.done {
+                let rest = if self.done {
+                    // Anything left over is a truncated object, which the parser will complain about.
+                    self.pending.len()
+                } else {
+                    end
+                };
+                let remainder = self.pending.split_off(rest);
+                let objects = std::mem::replace(&mut self.pending, remainder);
+                return Ok((objects, count + (rest > end) as usize));
+            }
+            self.read_top_level_object(reader)?;
+        }
+    }
+
+    /// End of the last complete object in the pending data and the number of objects up to there.
+    fn complete_objects(&self) -> (usize, usize) {
+        let mut pos = 0;
+        let mut count = 0;
+        loop {
+            // Garbage between objects is handed out with the next object, the parser skips it.
+            let Some(start) = find_signature(&self.pending, pos) else {
+                return (pos, count);
+            };
+            let Some((size, object_type)) = object_size_and_type(&self.pending[start..]) else {
+                return (pos, count);
+            };
+            let next = start + object_stride(size.max(BASE_HEADER_SIZE), object_type);
+            if next > self.pending.len() {
+                return (pos, count);
+            }
+            pos = next;
+            count += 1;
+        }
+    }
+
+    /// Reads the next object of the file, unpacking it if it is a log container.
+    fn read_top_level_object<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut base = [0u8; BASE_HEADER_SIZE];
+        match reader.read_exact(&mut base) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.done = true;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        if &base[..4] != OBJECT_SIGNATURE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing BLF object signature",
+            ));
+        }
+        let (size, object_type) = object_size_and_type(&base).unwrap();
+        let mut body = Vec::with_capacity(size.saturating_sub(BASE_HEADER_SIZE));
+        reader
+            .by_ref()
+            .take(size.saturating_sub(BASE_HEADER_SIZE) as u64)
+            .read_to_end(&mut body)?;
+        let padding = object_stride(size, object_type) - size;
+        io::copy(&mut reader.by_ref().take(padding as u64), &mut io::sink())?;
+
+        if object_type != LOG_CONTAINER {
+            self.pending.extend_from_slice(&base);
+            self.pending.extend_from_slice(&body);
+            let padded = object_stride(size, object_type);
+            self.pending.resize(self.pending.len() + padded - size, 0);
+            return Ok(());
+        }
+
+        // Compression method, 6 reserved bytes, uncompressed size and 4 more reserved bytes
+        let method = u16_at(&body, 0).unwrap_or(NO_COMPRESSION);
+        let unpacked_size = u32_at(&body, 8).unwrap_or(0) as usize;
+        let packed = body.get(16..).unwrap_or_default();
+        match method {
+            NO_COMPRESSION => self.pending.extend_from_slice(packed),
+            ZLIB_DEFLATE => {
+                self.pending.reserve(unpacked_size);
+                ZlibDecoder::new(packed).read_to_end(&mut self.pending)?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown BLF compression method {method}"),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn start_of_measurement(header: &BlfHeader) -> u64 {
+    match header.start {
+        Some(start) => {
+            start.num_seconds_from_midnight() as u64 * 1_000_000_000 + start.nanosecond() as u64
+        }
+        None => 0,
+    }
+}
+
+/// Turns a frame into a message, keeping only the first 8 data bytes of CAN FD frames.
+fn frame(header: &BlfHeader, nanos: u64, raw_id: u32, data: &[u8]) -> LogLine {
+    let mut bytes = [0u8; 8];
+    let len = data.len().min(8);
+    bytes[..len].copy_from_slice(&data[..len]);
+    LogLine {
+        hex_id: raw_id & !EXTENDED_ID_FLAG,
+        value: f32::NAN,
+        time: LogTime::Clock(ClockTime::from_nanos(start_of_measurement(header) + nanos)),
+        payload: Some(CanPayload {
+            dlc: len as u8,
+            data: bytes,
+        }),
+    }
+}
+
+/// Parses a single object. Only CAN data frames yield messages, other objects are skipped.
+fn parse_object(object: &[u8], header: &BlfHeader) -> Result<Option<LogLine>, String> {
+    let truncated = || "truncated object".to_string();
+    let header_size = u16_at(object, 4).ok_or_else(truncated)? as usize;
+    let header_version = u16_at(object, 6).ok_or_else(truncated)?;
+    let (_, object_type) = object_size_and_type(object).ok_or_else(truncated)?;
+    if !matches!(
+        object_type,
+        CAN_MESSAGE | CAN_MESSAGE2 | CAN_FD_MESSAGE | CAN_FD_MESSAGE_64
+    ) {
+        return Ok(None);
+    }
+    // Both header versions have the flags and the timestamp at the same place.
+    if !matches!(header_version, 1 | 2) {
+        return Err(format!("unknown object header version {header_version}"));
+    }
+    let flags = u32_at(object, 16).ok_or_else(truncated)?;
+    let timestamp = u64_at(object, 24).ok_or_else(truncated)?;
+    let nanos = match flags {
+        TIME_TEN_MICS => timestamp * 10_000,
+        _ => timestamp,
+    };
+    let data = object.get(header_size..).ok_or_else(truncated)?;
+
+    match object_type {
+        // Channel, flags, DLC, ID and 8 data bytes
+        CAN_MESSAGE | CAN_MESSAGE2 => {
+            let frame_flags = *data.get(2).ok_or_else(truncated)?;
+            let dlc = *data.get(3).ok_or_else(truncated)? as usize;
+            let id = u32_at(data, 4).ok_or_else(truncated)?;
+            let bytes = data.get(8..16).ok_or_else(truncated)?;
+            if frame_flags & REMOTE_FLAG != 0 {
+                return Ok(None);
+            }
+            Ok(Some(frame(header, nanos, id, &bytes[..dlc.min(8)])))
+        }
+        // Channel, flags, DLC, ID, frame length, bit count, FD flags, valid data bytes, 5 reserved
+        // bytes and 64 data bytes
+        CAN_FD_MESSAGE => {
+            let frame_flags = *data.get(2).ok_or_else(truncated)?;
+            let id = u32_at(data, 4).ok_or_else(truncated)?;
+            let valid_bytes = *data.get(14).ok_or_else(truncated)? as usize;
+            let bytes = data
+                .get(20..20 + valid_bytes.min(64))
+                .ok_or_else(truncated)?;
+            if frame_flags & REMOTE_FLAG != 0 {
+                return Ok(None);
+            }
+            Ok(Some(frame(header, nanos, id, bytes)))
+        }
+        // Channel, DLC, valid data bytes, Tx count, ID, frame length, flags, bit rate settings,
+        // bit timings, bit count, direction, offset of extended data and CRC, then the data bytes
+        _ => {
+            let dlc = *data.get(1).ok_or_else(truncated)? as usize;
+            let valid_bytes = *data.get(2).ok_or_else(truncated)? as usize;
+            let id = u32_at(data, 4).ok_or_else(truncated)?;
+            let frame_flags = u32_at(data, 12).ok_or_else(truncated)?;
+            if frame_flags & REMOTE_FLAG_64 != 0 {
+                return Ok(None);
+            }
+            let len = valid_bytes.min(FD_LENGTHS[dlc.min(15)]);
+            let bytes = data.get(40..40 + len).ok_or_else(truncated)?;
+            Ok(Some(frame(header, nanos, id, bytes)))
+        }
+    }
+}
+
+/// Parses unpacked objects of a Vector BLF log, as handed out by the reader of a [`BlfHeader`] log.
+///
+/// Only CAN data frames yield messages. Their value is NaN, as it has to be decoded from the payload
+/// with the CAN ID's scale. Data between objects is skipped with an error.
+pub fn parse_blf_objects<P: AsRef<Path>>(
+    data: &[u8],
+    header: &BlfHeader,
+    log_file: &P,
+    first_object_nr: usize,
+) -> Vec<Result<LogLine, ParserError>> {
+    let mut lines = vec![];
+    let mut pos = 0;
+    let mut object_nr = first_object_nr;
+    let bad_object = |object_nr, kind| ParserError::BadObject {
+        path: log_file.as_ref().to_path_buf(),
+        object_nr,
+        kind,
+    };
+    while pos < data.len() {
+        let Some(start) = find_signature(data, pos) else {
+            lines.push(Err(bad_object(
+                object_nr,
+                "missing object signature".to_string(),
+            )));
+            break;
+        };
+        if start > pos {
+            lines.push(Err(bad_object(
+                object_nr,
+                format!("{} bytes without object signature", start - pos),
+            )));
+        }
+        let object = &data[start..];
+        let next = match object_size_and_type(object) {
+            Some((size, object_type)) => {
+                start + object_stride(size.max(BASE_HEADER_SIZE), object_type)
+            }
+            None => data.len(),
+        };
+        let object = &data[start..next.min(data.len())];
+        match parse_object(object, header) {
+            Ok(Some(log_line)) => lines.push(Ok(log_line)),
+            Ok(None) => {}
+            Err(kind) => lines.push(Err(bad_object(object_nr, kind))),
+        }
+        pos = next;
+        object_nr += 1;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    /// A file header starting 2024-03-01 12:00:00.250.
+    fn file_header(object_count: u32) -> Vec<u8> {
+        let mut header = vec![0u8; 144];
+        header[..4].copy_from_slice(blf_header::FILE_SIGNATURE);
+        header[4..8].copy_from_slice(&144u32.to_le_bytes());
+        header[32..36].copy_from_slice(&object_count.to_le_bytes());
+        for (nr, field) in [2024u16, 3, 5, 1, 12, 0, 0, 250].iter().enumerate() {
+            header[40 + 2 * nr..42 + 2 * nr].copy_from_slice(&field.to_le_bytes());
+        }
+        header
+    }
+
+    /// An object with a version 1 header, padded like in a file.
+    fn object(object_type: u32, flags: u32, timestamp: u64, body: &[u8]) -> Vec<u8> {
+        let size = 32 + body.len();
+        let mut object = Vec::new();
+        object.extend_from_slice(OBJECT_SIGNATURE);
+        object.extend_from_slice(&32u16.to_le_bytes());
+        object.extend_from_slice(&1u16.to_le_bytes());
+        object.extend_from_slice(&(size as u32).to_le_bytes());
+        object.extend_from_slice(&object_type.to_le_bytes());
+        object.extend_from_slice(&flags.to_le_bytes());
+        // Client index and object version
+        object.extend_from_slice(&[0; 4]);
+        object.extend_from_slice(&timestamp.to_le_bytes());
+        object.extend_from_slice(body);
+        object.resize(object_stride(size, object_type), 0);
+        object
+    }
+
+    fn can_message(frame_flags: u8, id: u32, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0u8; 16];
+        body[2] = frame_flags;
+        body[3] = data.len() as u8;
+        body[4..8].copy_from_slice(&id.to_le_bytes());
+        body[8..8 + data.len()].copy_from_slice(data);
+        // 10 µs steps
+        object(CAN_MESSAGE, TIME_TEN_MICS, 150, &body)
+    }
+
+    fn container(objects: &[u8], compress: bool) -> Vec<u8> {
+        let (method, packed) = if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(objects).unwrap();
+            (ZLIB_DEFLATE, encoder.finish().unwrap())
+        } else {
+            (NO_COMPRESSION, objects.to_vec())
+        };
+        let mut body = vec![0u8; 16];
+        body[..2].copy_from_slice(&method.to_le_bytes());
+        body[8..12].copy_from_slice(&(objects.len() as u32).to_le_bytes());
+        body.extend_from_slice(&packed);
+        // Containers have a base header only
+        let size = BASE_HEADER_SIZE + body.len();
+        let mut container = Vec::new();
+        container.extend_from_slice(OBJECT_SIGNATURE);
+        container.extend_from_slice(&16u16.to_le_bytes());
+        container.extend_from_slice(&1u16.to_le_bytes());
+        container.extend_from_slice(&(size as u32).to_le_bytes());
+        container.extend_from_slice(&LOG_CONTAINER.to_le_bytes());
+        container.extend_from_slice(&body);
+        container.resize(size + size % 4, 0);
+        container
+    }
+
+    fn header() -> BlfHeader {
+        BlfHeader::parse(&file_header(2)).unwrap()
+    }
+
+    /// Nanoseconds since midnight of the start of [`header`], plus `nanos`.
+    fn time(nanos: u64) -> LogTime {
+        LogTime::Clock(ClockTime::from_nanos(
+            12 * 3_600_000_000_000 + 250_000_000 + nanos,
+        ))
+    }
+
+    #[test]
+    fn parses_the_file_header() {
+        let header = header();
+        let start = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_milli_opt(12, 0, 0, 250)
+            .unwrap();
+        assert_eq!(header.start, Some(start));
+        assert_eq!(header.header_size, 144);
+        assert_eq!(header.object_count, 2);
+        assert!(BlfHeader::parse(b"LOGG").is_none());
+    }
+
+    #[test]
+    fn parses_can_messages() {
+        let data = can_message(0, 0x100C_0000 | EXTENDED_ID_FLAG, &[0x2E, 0, 0, 1]);
+        let lines = parse_blf_objects(&data, &header(), &"test.blf", 0);
+        let [Ok(line)] = &lines[..] else {
+            panic!("{lines:?}");
+        };
+        assert_eq!(line.hex_id, 0x100C_0000);
+        assert!(line.value.is_nan());
+        assert_eq!(line.time, time(1_500_000));
+        let payload = line.payload.unwrap();
+        assert_eq!(payload.bytes(), [0x2E, 0, 0, 1]);
+    }
+
+    #[test]
+    fn parses_can_fd_64_messages() {
+        let mut body = vec![0u8; 40];
+        // DLC 9 is 12 data bytes
+        body[1] = 9;
+        body[2] = 12;
+        body[4..8].copy_from_slice(&0x123u32.to_le_bytes());
+        body.extend(1..=12u8);
+        let data = object(CAN_FD_MESSAGE_64, 2, 7, &body);
+        let lines = parse_blf_objects(&data, &header(), &"test.blf", 0);
+        let [Ok(line)] = &lines[..] else {
+            panic!("{lines:?}");
+        };
+        assert_eq!(line.hex_id, 0x123);
+        assert_eq!(line.time, time(7));
+        assert_eq!(line.payload.unwrap().bytes(), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn skips_remote_frames_and_reports_garbage() {
+        let mut data = can_message(REMOTE_FLAG, 0x200, &[]);
+        data.extend_from_slice(b"junk");
+        data.extend(can_message(0, 0x300, &[1]));
+        let lines = parse_blf_objects(&data, &header(), &"test.blf", 5);
+        match &lines[..] {
+            [Err(ParserError::BadObject { object_nr: 6, .. }), Ok(line)] => {
+                assert_eq!(line.hex_id, 0x300)
+            }
+            lines => panic!("{lines:?}"),
+        }
+    }
+
+    #[test]
+    fn unpacks_objects_split_between_containers() {
+        let objects = [can_message(0, 0x100, &[1, 2]), can_message(0, 0x200, &[3])].concat();
+        let split = objects.len() - 10;
+        let mut file = file_header(2);
+        file.extend(container(&objects[..split], true));
+        file.extend(container(&objects[split..], false));
+
+        let mut unpacker = BlfUnpacker::default();
+        let mut reader = &file[..];
+        let (first, count) = unpacker.read_objects(&mut reader, 1).unwrap();
+        assert_eq!(count, 1);
+        let (second, count) = unpacker.read_objects(&mut reader, 1).unwrap();
+        assert_eq!(count, 1);
+        let (rest, count) = unpacker.read_objects(&mut reader, 1).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(count, 0);
+
+        let ids: Vec<u32> = [first, second]
+            .iter()
+            .flat_map(|objects| parse_blf_objects(objects, &header(), &"test.blf", 0))
+            .map(|line| line.unwrap().hex_id)
+            .collect();
+        assert_eq!(ids, [0x100, 0x200]);
+    }
+
+    #[test]
+    fn reads_unpadded_can_fd_64_messages_outside_containers() {
+        let mut body = vec![0u8; 40];
+        body[1] = 1;
+        body[2] = 1;
+        body[4..8].copy_from_slice(&0x123u32.to_le_bytes());
+        body.push(0xAB);
+        let fd = object(CAN_FD_MESSAGE_64, 2, 7, &body);
+        assert_eq!(fd.len() % 4, 1);
+        let mut file = file_header(2);
+        file.extend(fd);
+        file.extend(can_message(0, 0x200, &[3]));
+
+        let mut unpacker = BlfUnpacker::default();
+        let mut reader = &file[..];
+        let (objects, count) = unpacker.read_objects(&mut reader, usize::MAX).unwrap();
+        assert_eq!(count, 2);
+        let ids: Vec<u32> = parse_blf_objects(&objects, &header(), &"test.blf", 0)
+            .into_iter()
+            .map(|line| line.unwrap().hex_id)
+            .collect();
+        assert_eq!(ids, [0x123, 0x200]);
+    }
+}
@@ -0,0 +1,378 @@
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use arrow::record_batch::RecordBatch;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Stdout, Write};
+use std::path::{Path, PathBuf};
+
+use super::arrow_tables::{
+    comments_batch, comments_schema, id_batch, id_schema, long_batch, long_schema,
+};
+use super::{
+    comments_path, dataset_name, id_metadata, is_stdout, remember_ids, CanMeta, CanWriter,
+    WriterError,
+};
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+/// The long table, either an IPC file or an IPC stream to the standard output.
+enum LongTable {
+    File(FileWriter<BufWriter<File>>),
+    Stream(StreamWriter<BufWriter<Stdout>>),
+}
+
+impl LongTable {
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), WriterError> {
+        match self {
+            LongTable::File(table) => table.write(batch)?,
+            LongTable::Stream(table) => table.write(batch)?,
+        }
+        Ok(())
+    }
+}
+
+fn create_file<P: AsRef<Path>>(
+    path: &P,
+    schema: &SchemaRef,
+) -> Result<FileWriter<BufWriter<File>>, WriterError> {
+    let file = File::create(path).map_err(|e| WriterError::io(path, e))?;
+    Ok(FileWriter::try_new_buffered(file, schema)?)
+}
+
+fn close_file<P: AsRef<Path>>(
+    path: &P,
+    table: FileWriter<BufWriter<File>>,
+) -> Result<(), WriterError> {
+    table
+        .into_inner()?
+        .flush()
+        .map_err(|e| WriterError::io(path, e))
+}
+
+/// Writes CAN messages to Arrow IPC (Feather V2) files while they are parsed. Batches are not
+/// compressed, so the files can be memory-mapped.
+///
+/// By default, all messages go into a single long table with the columns hex_id (u32), str_id (String),
+/// ts (u64) and value (f32). The unit, scale and description of every CAN ID written are stored in the
+/// custom metadata in the footer as "<str_id>.unit" and so on, like the key-value metadata of Parquet.
+/// For the output path `-`, the table is streamed to the standard output in the IPC stream format,
+/// which has no footer, so the metadata of the CAN IDs known when the first batch is written is stored
+/// in the schema metadata instead.
+///
+/// With [`ArrowIpcWriter::with_file_per_id`], the output path is a directory holding a file per CAN ID
+/// with the columns ts and value, and the CAN ID's metadata as field metadata of the value.
+///
+/// Comments are written to a sibling table with the columns id (u32), ts (u64) and comment (String),
+/// "<output>.comments.arrow" or "COMMENTS.arrow" in the output directory. The information about the
+/// conversion (and, for a long table file, about all CAN IDs written) is added to the custom metadata
+/// in the footer of every file.
+pub struct ArrowIpcWriter {
+    output_path: PathBuf,
+    file_per_id: bool,
+    /// Long table, created when the first batch is written
+    table: Option<(LongTable, SchemaRef)>,
+    /// By CAN ID, when writing a file per ID
+    id_tables: HashMap<u32, (FileWriter<BufWriter<File>>, PathBuf)>,
+    /// CAN IDs written so far
+    written_ids: BTreeMap<u32, CanId>,
+    /// Files and directories created so far
+    created_paths: Vec<PathBuf>,
+}
+
+impl ArrowIpcWriter {
+    /// Files are only created once messages (or comments) are written.
+    pub fn new<P: AsRef<Path>>(output_path: &P) -> Self {
+        Self {
+            output_path: output_path.as_ref().to_path_buf(),
+            file_per_id: false,
+            table: None,
+            id_tables: HashMap::new(),
+            written_ids: BTreeMap::new(),
+            created_paths: vec![],
+        }
+    }
+
+    /// Write a file per CAN ID into the output directory instead of a single long table.
+    pub fn with_file_per_id(mut self, file_per_id: bool) -> Self {
+        self.file_per_id = file_per_id;
+        self
+    }
+
+    /// Number of CAN IDs written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.written_ids.len()
+    }
+
+    /// Path of the table holding the comments.
+    pub fn comments_path(&self) -> PathBuf {
+        comments_path(&self.output_path, self.file_per_id, "arrow")
+    }
+
+    /// Files and directories created so far, the output directory only if it did not exist before.
+    pub fn created_paths(&self) -> Vec<PathBuf> {
+        self.created_paths.clone()
+    }
+
+    fn create_output_dir(&mut self) -> Result<(), WriterError> {
+        if self.output_path.is_dir() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.output_path)
+            .map_err(|e| WriterError::io(&self.output_path, e))?;
+        self.created_paths.push(self.output_path.clone());
+        Ok(())
+    }
+
+    fn create_long_table(&mut self, can_ids: &HashMap<u32, CanId>) -> Result<(), WriterError> {
+        if self.table.is_none() {
+            let table = if is_stdout(&self.output_path) {
+                let metadata = can_ids
+                    .values()
+                    .chain(self.written_ids.values())
+                    .flat_map(|can_id| {
+                        let name = dataset_name(can_id);
+                        id_metadata(can_id)
+                            .into_iter()
+                            .map(move |(key, value)| (format!("{name}.{key}"), value))
+                    })
+                    .collect();
+                let schema = long_schema(metadata);
+                let stdout = BufWriter::new(std::io::stdout());
+                (
+                    LongTable::Stream(StreamWriter::try_new(stdout, &schema)?),
+                    schema,
+                )
+            } else {
+                let schema = long_schema(HashMap::new());
+                let table = create_file(&self.output_path, &schema)?;
+                self.created_paths.push(self.output_path.clone());
+                (LongTable::File(table), schema)
+            };
+            self.table = Some(table);
+        }
+        Ok(())
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        remember_ids(&mut self.written_ids, can_msgs, can_ids);
+        if self.file_per_id {
+            let mut sorted = can_msgs.to_vec();
+            sorted.sort();
+            for msgs in sorted.chunk_by(|a, b| a.hex_id == b.hex_id) {
+                self.append(msgs)?;
+            }
+            return Ok(());
+        }
+
+        self.create_long_table(can_ids)?;
+        let (table, schema) = self.table.as_mut().unwrap();
+        if !can_msgs.is_empty() {
+            table.write(&long_batch(schema, can_msgs, &self.written_ids)?)?;
+        }
+        Ok(())
+    }
+
+    /// Appends messages which all belong to the same, already known, CAN ID to its own file.
+    fn append(&mut self, can_msgs: &[CanMsg]) -> Result<(), WriterError> {
+        let hex_id = can_msgs[0].hex_id;
+        if !self.id_tables.contains_key(&hex_id) {
+            self.create_output_dir()?;
+            let can_id = &self.written_ids[&hex_id];
+            let path = self
+                .output_path
+                .join(format!("{}.arrow", dataset_name(can_id).replace('/', "_")));
+            let table = create_file(&path, &id_schema(can_id))?;
+            log::debug!("Created {:?}", path.as_os_str());
+            self.created_paths.push(path.clone());
+            self.id_tables.insert(hex_id, (table, path));
+        }
+        let (table, _) = self.id_tables.get_mut(&hex_id).unwrap();
+        let batch = id_batch(table.schema(), can_msgs)?;
+        table.write(&batch)?;
+        Ok(())
+    }
+
+    /// Writes the comments to their table and the information about the conversion (and, for a long
+    /// table file, about the CAN IDs) to the footers, closing all files.
+    ///
+    /// Streaming to the standard output, there is neither a footer nor a place for the comments.
+    pub fn finish(mut self, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        let entries = meta.entries(self.dataset_count());
+
+        if self.file_per_id {
+            self.create_output_dir()?;
+            for (_, (mut table, path)) in self.id_tables.drain() {
+                for (key, value) in &entries {
+                    table.write_metadata(key, value);
+                }
+                close_file(&path, table)?;
+            }
+        } else {
+            self.create_long_table(&HashMap::new())?;
+            let (table, schema) = self.table.take().unwrap();
+            match table {
+                LongTable::File(mut table) => {
+                    for can_id in self.written_ids.values() {
+                        let name = dataset_name(can_id);
+                        for (key, value) in id_metadata(can_id) {
+                            table.write_metadata(format!("{name}.{key}"), value);
+                        }
+                    }
+                    for (key, value) in &entries {
+                        table.write_metadata(key, value);
+                    }
+                    close_file(&self.output_path, table)?;
+                }
+                LongTable::Stream(table) => {
+                    let missing = self
+                        .written_ids
+                        .values()
+                        .filter(|can_id| {
+                            let key = format!("{}.hex_id", dataset_name(can_id));
+                            !schema.metadata().contains_key(&key)
+                        })
+                        .count();
+                    if missing > 0 {
+                        log::warn!(
+                            "{missing} CAN IDs first seen after the stream started have no metadata in its schema"
+                        );
+                    }
+                    table
+                        .into_inner()?
+                        .flush()
+                        .map_err(|e| WriterError::io(&self.output_path, e))?;
+                }
+            }
+        }
+
+        if !can_cmts.is_empty() {
+            if is_stdout(&self.output_path) {
+                log::warn!("Comments are not written when streaming to the standard output");
+                return Ok(());
+            }
+            let comments_path = self.comments_path();
+            let mut table = create_file(&comments_path, &comments_schema())?;
+            table.write(&comments_batch(can_cmts)?)?;
+            for (key, value) in &entries {
+                table.write_metadata(key, value);
+            }
+            close_file(&comments_path, table)?;
+            log::debug!("Wrote comments to {:?}", comments_path.as_os_str());
+        }
+        Ok(())
+    }
+}
+
+impl CanWriter for ArrowIpcWriter {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        ArrowIpcWriter::write_batch(self, can_msgs, can_ids)
+    }
+
+    fn dataset_count(&self) -> usize {
+        ArrowIpcWriter::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        ArrowIpcWriter::created_paths(self)
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        ArrowIpcWriter::finish(*self, can_cmts, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::ipc::reader::FileReader;
+
+    fn msg(hex_id: u32, ts: u64, value: f32) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ts,
+            value,
+            payload: None,
+        }
+    }
+
+    fn can_id(hex_id: u32, str_id: &str, unit: &str, scale: f32) -> CanId {
+        CanId {
+            hex_id,
+            str_id: Some(str_id.to_string()),
+            unit: Some(unit.to_string()),
+            scale: Some(scale),
+            ..Default::default()
+        }
+    }
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("can-parser-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes PRESSURE in the first batch and FLOW, which is only known from then on, in the second.
+    fn write_two_batches(writer: &mut ArrowIpcWriter) {
+        let mut can_ids = HashMap::from([(1, can_id(1, "PRESSURE", "mmHg", 0.5))]);
+        writer.write_batch(&[msg(1, 1_000, 1.5)], &can_ids).unwrap();
+        can_ids.insert(2, can_id(2, "FLOW", "L/min", 0.25));
+        writer
+            .write_batch(&[msg(2, 1_500, 7.0), msg(1, 2_000, 2.5)], &can_ids)
+            .unwrap();
+    }
+
+    #[test]
+    fn stores_the_metadata_of_ids_from_all_batches_in_the_footer() {
+        let dir = output_dir("arrow-long");
+        let path = dir.join("out.arrow");
+        let mut writer = ArrowIpcWriter::new(&path);
+        write_two_batches(&mut writer);
+        writer.finish(&[], &CanMeta::for_tests()).unwrap();
+
+        let reader = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        let metadata = reader.custom_metadata();
+        assert_eq!(metadata["PRESSURE.unit"], "mmHg");
+        assert_eq!(metadata["PRESSURE.scale"], "0.5");
+        assert_eq!(metadata["FLOW.unit"], "L/min");
+        assert_eq!(metadata["FLOW.scale"], "0.25");
+        assert_eq!(metadata["Dataset count"], "2");
+        assert!(reader.schema().metadata().is_empty());
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stores_the_metadata_of_every_id_on_its_value_field() {
+        let dir = output_dir("arrow-per-id");
+        let path = dir.join("out");
+        let mut writer = ArrowIpcWriter::new(&path).with_file_per_id(true);
+        write_two_batches(&mut writer);
+        writer.finish(&[], &CanMeta::for_tests()).unwrap();
+
+        for (name, unit, scale, rows) in
+            [("PRESSURE", "mmHg", "0.5", 2), ("FLOW", "L/min", "0.25", 1)]
+        {
+            let file = File::open(path.join(format!("{name}.arrow"))).unwrap();
+            let reader = FileReader::try_new(file, None).unwrap();
+            let schema = reader.schema();
+            let value = schema.field_with_name("value").unwrap();
+            assert_eq!(value.metadata()["unit"], unit);
+            assert_eq!(value.metadata()["scale"], scale);
+            assert_eq!(reader.custom_metadata()["Dataset count"], "2");
+            let count: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+            assert_eq!(count, rows);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
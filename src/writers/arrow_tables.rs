@@ -0,0 +1,97 @@
+//! Tables shared by the writers based on Apache Arrow.
+
+use arrow::array::{ArrayRef, Float32Array, StringArray, UInt32Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use super::id_metadata;
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+/// Schema of the long table, holding the messages of all CAN IDs.
+pub(crate) fn long_schema(metadata: HashMap<String, String>) -> SchemaRef {
+    Arc::new(Schema::new_with_metadata(
+        vec![
+            Field::new("hex_id", DataType::UInt32, false),
+            Field::new("str_id", DataType::Utf8, true),
+            Field::new("ts", DataType::UInt64, false),
+            Field::new("value", DataType::Float32, false),
+        ],
+        metadata,
+    ))
+}
+
+/// Schema of the table of a single CAN ID, with its metadata on the value field.
+pub(crate) fn id_schema(can_id: &CanId) -> SchemaRef {
+    let metadata = id_metadata(can_id)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::UInt64, false),
+        Field::new("value", DataType::Float32, false).with_metadata(metadata),
+    ]))
+}
+
+pub(crate) fn comments_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("ts", DataType::UInt64, false),
+        Field::new("comment", DataType::Utf8, false),
+    ]))
+}
+
+/// Messages of arbitrary CAN IDs, which all have to be in `written_ids`.
+pub(crate) fn long_batch(
+    schema: &SchemaRef,
+    can_msgs: &[CanMsg],
+    written_ids: &BTreeMap<u32, CanId>,
+) -> Result<RecordBatch, ArrowError> {
+    let str_ids: Vec<Option<&str>> = can_msgs
+        .iter()
+        .map(|msg| written_ids[&msg.hex_id].str_id.as_deref())
+        .collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            can_msgs.iter().map(|msg| msg.hex_id),
+        )),
+        Arc::new(StringArray::from(str_ids)),
+        Arc::new(UInt64Array::from_iter_values(
+            can_msgs.iter().map(|msg| msg.ts),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            can_msgs.iter().map(|msg| msg.value),
+        )),
+    ];
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+/// Messages which all belong to the same CAN ID.
+pub(crate) fn id_batch(schema: &SchemaRef, can_msgs: &[CanMsg]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            can_msgs.iter().map(|msg| msg.ts),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            can_msgs.iter().map(|msg| msg.value),
+        )),
+    ];
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+pub(crate) fn comments_batch(can_cmts: &[CanCmt]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            can_cmts.iter().map(|cmt| cmt.id),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            can_cmts.iter().map(|cmt| cmt.ts),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            can_cmts.iter().map(|cmt| cmt.value.as_str()),
+        )),
+    ];
+    RecordBatch::try_new(comments_schema(), columns)
+}
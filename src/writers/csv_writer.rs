@@ -0,0 +1,398 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::{
+    comments_path, dataset_name, is_stdout, remember_ids, CanMeta, CanWriter, WriterError,
+};
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+/// How the values of a CAN ID are aligned to the rows of a wide table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alignment {
+    /// The last value received at or before the row's time.
+    #[default]
+    Hold,
+    /// Linear interpolation between the values received around the row's time, empty outside of them.
+    Interpolate,
+}
+
+impl fmt::Display for Alignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alignment::Hold => write!(f, "hold"),
+            Alignment::Interpolate => write!(f, "interpolate"),
+        }
+    }
+}
+
+impl FromStr for Alignment {
+    type Err = String;
+
+    /// Accepts "hold" or "interpolate".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hold" => Ok(Alignment::Hold),
+            "interpolate" => Ok(Alignment::Interpolate),
+            _ => Err(format!(
+                "Invalid alignment {s:?}, expected hold or interpolate"
+            )),
+        }
+    }
+}
+
+/// Formats nanoseconds as seconds, without trailing zeros.
+fn format_seconds(ts: u64, decimal_separator: char) -> String {
+    let nanos = format!("{:09}", ts % 1_000_000_000);
+    let nanos = nanos.trim_end_matches('0');
+    if nanos.is_empty() {
+        return (ts / 1_000_000_000).to_string();
+    }
+    format!("{}{decimal_separator}{nanos}", ts / 1_000_000_000)
+}
+
+fn format_value(value: f32, decimal_separator: char) -> String {
+    let value = value.to_string();
+    match decimal_separator {
+        '.' => value,
+        separator => value.replace('.', &separator.to_string()),
+    }
+}
+
+/// Value of a series sorted by time at time `ts`, where `cursor` is the index of the last entry at or
+/// before the previous row's time.
+fn aligned_value(
+    series: &[(u64, f32)],
+    cursor: &mut Option<usize>,
+    ts: u64,
+    alignment: Alignment,
+) -> Option<f32> {
+    let mut next = cursor.map_or(0, |i| i + 1);
+    while next < series.len() && series[next].0 <= ts {
+        *cursor = Some(next);
+        next += 1;
+    }
+    let (prev_ts, prev_value) = series[(*cursor)?];
+    match alignment {
+        Alignment::Hold => Some(prev_value),
+        Alignment::Interpolate if prev_ts == ts => Some(prev_value),
+        Alignment::Interpolate => {
+            let (next_ts, next_value) = *series.get(next)?;
+            let fraction = (ts - prev_ts) as f64 / (next_ts - prev_ts) as f64;
+            let value = prev_value as f64 + (next_value as f64 - prev_value as f64) * fraction;
+            Some(value as f32)
+        }
+    }
+}
+
+/// Times of the entries of series sorted by time, merged in order and without duplicates while they are
+/// iterated.
+fn merged_times<'a>(series: Vec<&'a [(u64, f32)]>) -> impl Iterator<Item = u64> + 'a {
+    // Next entry of every series by its time, the number of the series and its index
+    let mut heads: BinaryHeap<Reverse<(u64, usize, usize)>> = series
+        .iter()
+        .enumerate()
+        .filter_map(|(i, entries)| Some(Reverse((entries.first()?.0, i, 0))))
+        .collect();
+    let mut last_ts = None;
+    std::iter::from_fn(move || loop {
+        let Reverse((ts, i, j)) = heads.pop()?;
+        if let Some((next_ts, _)) = series[i].get(j + 1) {
+            heads.push(Reverse((*next_ts, i, j + 1)));
+        }
+        if last_ts != Some(ts) {
+            last_ts = Some(ts);
+            return Some(ts);
+        }
+    })
+}
+
+/// Writes CAN messages to CSV, e.g. for spreadsheets.
+///
+/// By default, the messages are written while they are parsed as a long table with the columns hex_id,
+/// str_id, time (in seconds) and value.
+///
+/// With [`CsvWriter::with_wide_table`], the messages are kept until [`CsvWriter::finish`] writes a wide
+/// table with a column per CAN ID, named by its str_id, and a second header row with the units. The
+/// rows are at every time a message was received or, with [`CsvWriter::with_time_step`], at a fixed
+/// interval from the first message on, with the values aligned as configured.
+///
+/// With `,` as decimal separator, fields are separated by `;`. Comments are written to
+/// "<output>.comments.csv" with the columns id, time and comment, unless writing to the standard output.
+pub struct CsvWriter {
+    output_path: PathBuf,
+    /// Until the table is started
+    output: Option<Box<dyn Write>>,
+    table: Option<csv::Writer<Box<dyn Write>>>,
+    decimal_separator: char,
+    wide_table: bool,
+    alignment: Alignment,
+    /// In ns
+    time_step: Option<u64>,
+    /// Messages by CAN ID as time and value, when writing a wide table
+    series: BTreeMap<u32, Vec<(u64, f32)>>,
+    /// CAN IDs written so far
+    written_ids: BTreeMap<u32, CanId>,
+}
+
+impl CsvWriter {
+    /// Creates the file, or writes to the standard output for `-`.
+    pub fn create<P: AsRef<Path>>(output_path: &P) -> Result<Self, WriterError> {
+        let output: Box<dyn Write> = if is_stdout(output_path) {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            let file = File::create(output_path).map_err(|e| WriterError::io(output_path, e))?;
+            Box::new(BufWriter::new(file))
+        };
+        Ok(Self {
+            output_path: output_path.as_ref().to_path_buf(),
+            output: Some(output),
+            table: None,
+            decimal_separator: '.',
+            wide_table: false,
+            alignment: Alignment::default(),
+            time_step: None,
+            series: BTreeMap::new(),
+            written_ids: BTreeMap::new(),
+        })
+    }
+
+    /// Separate decimals by `decimal_separator`, and fields by `;` if it is `,`.
+    pub fn with_decimal_separator(mut self, decimal_separator: char) -> Self {
+        self.decimal_separator = decimal_separator;
+        self
+    }
+
+    /// Write a wide table with a column per CAN ID instead of a long table.
+    pub fn with_wide_table(mut self, wide_table: bool) -> Self {
+        self.wide_table = wide_table;
+        self
+    }
+
+    /// How values are aligned to the rows of the wide table.
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Rows of the wide table every `time_step` ns, instead of at every time a message was received.
+    pub fn with_time_step(mut self, time_step: Option<u64>) -> Self {
+        self.time_step = time_step.filter(|&step| step > 0);
+        self
+    }
+
+    /// Number of CAN IDs written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.written_ids.len()
+    }
+
+    /// Path of the table holding the comments.
+    pub fn comments_path(&self) -> PathBuf {
+        comments_path(&self.output_path, false, "csv")
+    }
+
+    fn csv_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        if self.decimal_separator == ',' {
+            builder.delimiter(b';');
+        }
+        builder
+    }
+
+    fn table(&mut self) -> &mut csv::Writer<Box<dyn Write>> {
+        if self.table.is_none() {
+            let output = self.output.take().unwrap();
+            self.table = Some(self.csv_builder().from_writer(output));
+        }
+        self.table.as_mut().unwrap()
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        remember_ids(&mut self.written_ids, can_msgs, can_ids);
+        if self.wide_table {
+            for msg in can_msgs {
+                self.series
+                    .entry(msg.hex_id)
+                    .or_default()
+                    .push((msg.ts, msg.value));
+            }
+            return Ok(());
+        }
+
+        if self.table.is_none() {
+            self.table()
+                .write_record(["hex_id", "str_id", "time", "value"])?;
+        }
+        let table = self.table.as_mut().unwrap();
+        for msg in can_msgs {
+            let str_id = self.written_ids[&msg.hex_id].str_id.as_deref();
+            table.write_record([
+                msg.hex_id.to_string().as_str(),
+                str_id.unwrap_or_default(),
+                &format_seconds(msg.ts, self.decimal_separator),
+                &format_value(msg.value, self.decimal_separator),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn write_wide_table(&mut self) -> Result<(), WriterError> {
+        for series in self.series.values_mut() {
+            series.sort_by_key(|(ts, _)| *ts);
+        }
+        let mut header = vec!["time".to_string()];
+        let mut units = vec!["s".to_string()];
+        for hex_id in self.series.keys() {
+            let can_id = &self.written_ids[hex_id];
+            header.push(dataset_name(can_id));
+            units.push(can_id.unit.clone().unwrap_or_default());
+        }
+        self.table().write_record(&header)?;
+        self.table().write_record(&units)?;
+
+        let first_ts = self
+            .series
+            .values()
+            .filter_map(|s| s.first())
+            .map(|e| e.0)
+            .min();
+        let last_ts = self
+            .series
+            .values()
+            .filter_map(|s| s.last())
+            .map(|e| e.0)
+            .max();
+        let (Some(first_ts), Some(last_ts)) = (first_ts, last_ts) else {
+            return Ok(());
+        };
+        let row_times: Box<dyn Iterator<Item = u64>> = match self.time_step {
+            Some(step) => Box::new((first_ts..=last_ts).step_by(step as usize)),
+            None => Box::new(merged_times(
+                self.series.values().map(Vec::as_slice).collect(),
+            )),
+        };
+
+        let table = self.table.as_mut().unwrap();
+        let mut cursors: Vec<Option<usize>> = vec![None; self.series.len()];
+        let mut record: Vec<String> = Vec::with_capacity(self.series.len() + 1);
+        for ts in row_times {
+            record.clear();
+            record.push(format_seconds(ts, self.decimal_separator));
+            for (series, cursor) in self.series.values().zip(cursors.iter_mut()) {
+                record.push(match aligned_value(series, cursor, ts, self.alignment) {
+                    Some(value) => format_value(value, self.decimal_separator),
+                    None => String::new(),
+                });
+            }
+            table.write_record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the wide table, if configured, and the comments. CSV has no place for the information
+    /// about the conversion.
+    pub fn finish(mut self, can_cmts: &[CanCmt], _meta: &CanMeta) -> Result<(), WriterError> {
+        if self.wide_table {
+            self.write_wide_table()?;
+        } else if self.table.is_none() {
+            self.table()
+                .write_record(["hex_id", "str_id", "time", "value"])?;
+        }
+        self.table()
+            .flush()
+            .map_err(|e| WriterError::io(&self.output_path, e))?;
+
+        if !can_cmts.is_empty() {
+            if is_stdout(&self.output_path) {
+                log::warn!("Comments are not written when writing to the standard output");
+                return Ok(());
+            }
+            let comments_path = self.comments_path();
+            let mut table = self.csv_builder().from_path(&comments_path)?;
+            table.write_record(["id", "time", "comment"])?;
+            for cmt in can_cmts {
+                table.write_record([
+                    cmt.id.to_string().as_str(),
+                    &format_seconds(cmt.ts, self.decimal_separator),
+                    cmt.value.as_str(),
+                ])?;
+            }
+            table
+                .flush()
+                .map_err(|e| WriterError::io(&comments_path, e))?;
+            log::debug!("Wrote comments to {:?}", comments_path.as_os_str());
+        }
+        Ok(())
+    }
+}
+
+impl CanWriter for CsvWriter {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        CsvWriter::write_batch(self, can_msgs, can_ids)
+    }
+
+    fn dataset_count(&self) -> usize {
+        CsvWriter::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        if is_stdout(&self.output_path) {
+            return vec![];
+        }
+        vec![self.output_path.clone()]
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        CsvWriter::finish(*self, can_cmts, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_times_in_order_without_duplicates() {
+        let a = [(1, 0.0), (4, 0.0), (4, 0.0), (9, 0.0)];
+        let b = [(2, 0.0), (4, 0.0), (10, 0.0)];
+        let times: Vec<u64> = merged_times(vec![&a, &[], &b]).collect();
+        assert_eq!(times, vec![1, 2, 4, 9, 10]);
+        assert_eq!(merged_times(vec![]).next(), None);
+    }
+
+    #[test]
+    fn aligns_values_to_row_times() {
+        let series = [(10, 1.0), (20, 3.0)];
+        let mut cursor = None;
+        assert_eq!(
+            aligned_value(&series, &mut cursor, 5, Alignment::Hold),
+            None
+        );
+        assert_eq!(
+            aligned_value(&series, &mut cursor, 15, Alignment::Interpolate),
+            Some(2.0)
+        );
+        assert_eq!(
+            aligned_value(&series, &mut cursor, 25, Alignment::Hold),
+            Some(3.0)
+        );
+        assert_eq!(
+            aligned_value(&series, &mut cursor, 30, Alignment::Interpolate),
+            None
+        );
+    }
+}
@@ -0,0 +1,612 @@
+use hdf5::types::VarLenUnicode;
+use hdf5::{Dataset, Group, H5Type, Location};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{dataset_name, CanMeta, CanWriter, WriterError};
+use crate::collection::{decode_signals, CanMsgCollection};
+use crate::parsers::{CanCmt, CanId, CanMsg, CanSignal, Multiplexing};
+
+/// Number of messages per chunk of a dataset.
+const CHUNK_SIZE: usize = 16384;
+
+/// The entries of the datasets. `H5Type` is derived with an impl inside a constant, which the
+/// non_local_definitions lint flags, so the lint is allowed for these types only.
+#[allow(non_local_definitions)]
+mod entries {
+    use hdf5::H5Type;
+
+    use crate::parsers::CanMsg;
+
+    /// An entry of a dataset in "CAN_IDs".
+    #[derive(H5Type, Debug, Clone, Copy)]
+    #[repr(C)]
+    pub(super) struct H5Msg {
+        pub(super) hex_id: u32,
+        pub(super) ts: u64,
+        pub(super) value: f32,
+    }
+
+    impl From<&CanMsg> for H5Msg {
+        fn from(msg: &CanMsg) -> Self {
+            Self {
+                hex_id: msg.hex_id,
+                ts: msg.ts,
+                value: msg.value,
+            }
+        }
+    }
+
+    /// An entry of a dataset in "CAN_RAW".
+    #[derive(H5Type, Debug, Clone, Copy)]
+    #[repr(C)]
+    pub(super) struct H5RawMsg {
+        pub(super) ts: u64,
+        pub(super) dlc: u8,
+        pub(super) data: [u8; 8],
+    }
+
+    /// An entry of a dataset in "CAN_SIGNALS".
+    #[derive(H5Type, Debug, Clone, Copy)]
+    #[repr(C)]
+    pub(super) struct H5SignalValue {
+        pub(super) ts: u64,
+        pub(super) value: f64,
+    }
+}
+use entries::{H5Msg, H5RawMsg, H5SignalValue};
+
+fn create_str_attr(location: &Location, name: &str, value: &str) -> hdf5::Result<()> {
+    let attr = location.new_attr::<VarLenUnicode>().create(name)?;
+    let value_: VarLenUnicode = value.parse().unwrap();
+    attr.write_scalar(&value_)
+}
+
+/// Path of the dataset of a CAN ID when grouping by device: below the device (its name, "DEV<n>" or
+/// "common" for IDs without a device), in a group named by the prefix of the ID's name up to its last
+/// underscore, e.g. "devices/DEV3/CAN_ID_PRESSURE/CAN_ID_PRESSURE_SIG2".
+fn device_path(can_id: &CanId) -> String {
+    let device = match (&can_id.device_name, can_id.device) {
+        (Some(name), _) => name.replace('/', "_"),
+        (None, Some(device)) => format!("DEV{device}"),
+        (None, None) => "common".to_string(),
+    };
+    let name = match &can_id.derived_from {
+        Some(name) => name.to_owned(),
+        None => dataset_name(can_id),
+    };
+    match name.rsplit_once('_') {
+        Some((prefix, _)) if !prefix.is_empty() => format!("devices/{device}/{prefix}/{name}"),
+        _ => format!("devices/{device}/{name}"),
+    }
+}
+
+fn open_or_create_group(parent: &Group, name: &str) -> hdf5::Result<Group> {
+    match parent.group(name) {
+        Ok(group) => Ok(group),
+        Err(_) => parent.create_group(name),
+    }
+}
+
+/// Device number (and name) of IDs derived by an ID rule.
+fn create_device_attrs(location: &Location, can_id: &CanId) -> hdf5::Result<()> {
+    if let Some(device) = can_id.device {
+        location
+            .new_attr::<u32>()
+            .create("device")?
+            .write_scalar(&device)?;
+    }
+    if let Some(device_name) = &can_id.device_name {
+        create_str_attr(location, "device_name", device_name)?;
+    }
+    Ok(())
+}
+
+/// Appends `entries` to a resizable dataset currently holding `len` entries.
+fn append_to<T: H5Type>(dataset: &Dataset, len: &mut usize, entries: &[T]) -> hdf5::Result<()> {
+    let new_len = *len + entries.len();
+    dataset.resize(new_len)?;
+    dataset.write_slice(entries, *len..new_len)?;
+    *len = new_len;
+    Ok(())
+}
+
+/// Writes CAN messages to HDF5 while they are parsed.
+///
+/// Every CAN ID gets a chunked, resizable dataset in the "CAN_IDs" group, which is created when the first
+/// message of that ID is written. Messages are appended to the datasets batch by batch.
+///
+/// Optionally, the raw frames (DLC and data bytes) are written to datasets of the same name in the
+/// "CAN_RAW" group, for messages from logs which contain them.
+///
+/// Optionally, the datasets are grouped by device instead (see [`Hdf5Writer::with_device_groups`]),
+/// with soft links in "CAN_IDs" pointing to them.
+///
+/// The signals of CAN IDs with signal definitions are decoded from the raw frames into a dataset per
+/// signal, in a group named like the ID's dataset within the "CAN_SIGNALS" group. Multiplexed signals
+/// only get entries for the frames selecting them.
+pub struct Hdf5Writer {
+    root: hdf5::File,
+    ds_group: Group,
+    datasets: HashMap<u32, (Dataset, usize)>,
+    raw_payloads: bool,
+    raw_datasets: HashMap<u32, (Dataset, usize)>,
+    device_groups: bool,
+    /// By CAN ID and number of the signal
+    signal_datasets: HashMap<(u32, usize), (Dataset, usize)>,
+}
+
+impl Hdf5Writer {
+    pub fn create<P: AsRef<Path>>(output_path: &P) -> hdf5::Result<Self> {
+        let root = hdf5::File::create(output_path)?;
+        create_str_attr(&root, "created", chrono::Local::now().to_rfc3339().as_str())?;
+        let ds_group = root.create_group("CAN_IDs")?;
+        Ok(Self {
+            root,
+            ds_group,
+            datasets: HashMap::new(),
+            raw_payloads: false,
+            raw_datasets: HashMap::new(),
+            device_groups: false,
+            signal_datasets: HashMap::new(),
+        })
+    }
+
+    /// Also write the raw frames of messages to "CAN_RAW".
+    pub fn with_raw_payloads(mut self, raw_payloads: bool) -> Self {
+        self.raw_payloads = raw_payloads;
+        self
+    }
+
+    /// Store the datasets of CAN IDs in "devices/<device>/<prefix>/<name>" and soft links to them in
+    /// "CAN_IDs".
+    pub fn with_device_groups(mut self, device_groups: bool) -> Self {
+        self.device_groups = device_groups;
+        self
+    }
+
+    /// Number of datasets written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.datasets.len()
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> hdf5::Result<()> {
+        // Sorting is stable, messages of the same CAN ID thus keep their order.
+        let mut sorted = can_msgs.to_vec();
+        sorted.sort();
+        for msgs in sorted.chunk_by(|a, b| a.hex_id == b.hex_id) {
+            let hex_id = msgs[0].hex_id;
+            match can_ids.get(&hex_id) {
+                Some(can_id) => self.append(can_id, msgs)?,
+                None => self.append(&CanId::empty_with_id(hex_id), msgs)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends messages which all belong to `can_id`.
+    pub fn append(&mut self, can_id: &CanId, can_msgs: &[CanMsg]) -> hdf5::Result<()> {
+        if !self.datasets.contains_key(&can_id.hex_id) {
+            let dataset = self.create_dataset(can_id)?;
+            self.datasets.insert(can_id.hex_id, (dataset, 0));
+        }
+        let (dataset, len) = self.datasets.get_mut(&can_id.hex_id).unwrap();
+        let entries: Vec<H5Msg> = can_msgs.iter().map(H5Msg::from).collect();
+        append_to(dataset, len, &entries)?;
+
+        if self.raw_payloads {
+            let raw_entries: Vec<H5RawMsg> = can_msgs
+                .iter()
+                .filter_map(|msg| {
+                    msg.payload.map(|payload| H5RawMsg {
+                        ts: msg.ts,
+                        dlc: payload.dlc,
+                        data: payload.data,
+                    })
+                })
+                .collect();
+            if !raw_entries.is_empty() {
+                if !self.raw_datasets.contains_key(&can_id.hex_id) {
+                    let dataset = self.create_raw_dataset(can_id)?;
+                    self.raw_datasets.insert(can_id.hex_id, (dataset, 0));
+                }
+                let (dataset, len) = self.raw_datasets.get_mut(&can_id.hex_id).unwrap();
+                append_to(dataset, len, &raw_entries)?;
+            }
+        }
+
+        let decoded = decode_signals(can_id, can_msgs);
+        for (signal_nr, (signal, values)) in can_id.signals.iter().zip(decoded).enumerate() {
+            if values.ts.is_empty() {
+                continue;
+            }
+            let entries: Vec<H5SignalValue> = values
+                .ts
+                .into_iter()
+                .zip(values.values)
+                .map(|(ts, value)| H5SignalValue { ts, value })
+                .collect();
+            let key = (can_id.hex_id, signal_nr);
+            if !self.signal_datasets.contains_key(&key) {
+                let dataset = self.create_signal_dataset(can_id, signal)?;
+                self.signal_datasets.insert(key, (dataset, 0));
+            }
+            let (dataset, len) = self.signal_datasets.get_mut(&key).unwrap();
+            append_to(dataset, len, &entries)?;
+        }
+        Ok(())
+    }
+
+    fn create_signal_dataset(&self, can_id: &CanId, signal: &CanSignal) -> hdf5::Result<Dataset> {
+        let signals_group = open_or_create_group(&self.root, "CAN_SIGNALS")?;
+        let id_group = open_or_create_group(&signals_group, &dataset_name(can_id))?;
+        let dataset = id_group
+            .new_dataset::<H5SignalValue>()
+            .chunk(CHUNK_SIZE)
+            .deflate(5)
+            .shape(0..)
+            .create(signal.name.as_str())?;
+        dataset
+            .new_attr::<u32>()
+            .create("hex_id")?
+            .write_scalar(&can_id.hex_id)?;
+        create_str_attr(
+            &dataset,
+            "description",
+            signal.description.as_deref().unwrap_or("None"),
+        )?;
+        create_str_attr(&dataset, "unit", signal.unit.as_deref().unwrap_or("None"))?;
+        for (name, value) in [
+            ("scale", signal.factor),
+            ("offset", signal.offset),
+            ("min", signal.min),
+            ("max", signal.max),
+        ] {
+            dataset
+                .new_attr::<f64>()
+                .create(name)?
+                .write_scalar(&value)?;
+        }
+        create_device_attrs(&dataset, can_id)?;
+        if let Multiplexing::Multiplexed(value) = signal.multiplexing {
+            dataset
+                .new_attr::<u64>()
+                .create("multiplexer_value")?
+                .write_scalar(&value)?;
+        }
+        log::debug!("Created dataset {}/{}", dataset_name(can_id), signal.name);
+        Ok(dataset)
+    }
+
+    fn create_raw_dataset(&self, can_id: &CanId) -> hdf5::Result<Dataset> {
+        let raw_group = open_or_create_group(&self.root, "CAN_RAW")?;
+        let dataset = raw_group
+            .new_dataset::<H5RawMsg>()
+            .chunk(CHUNK_SIZE)
+            .deflate(5)
+            .shape(0..)
+            .create(dataset_name(can_id).as_str())?;
+        dataset
+            .new_attr::<u32>()
+            .create("hex_id")?
+            .write_scalar(&can_id.hex_id)?;
+        Ok(dataset)
+    }
+
+    fn create_dataset(&self, can_id: &CanId) -> hdf5::Result<Dataset> {
+        let str_id = dataset_name(can_id);
+
+        let dataset = if self.device_groups {
+            let path = device_path(can_id);
+            let (group_path, name) = path.rsplit_once('/').unwrap();
+            let dataset = open_or_create_group(&self.root, group_path)?
+                .new_dataset::<H5Msg>()
+                .chunk(CHUNK_SIZE)
+                .deflate(5)
+                .shape(0..)
+                .create(name)?;
+            self.ds_group.link_soft(&format!("/{path}"), &str_id)?;
+            dataset
+        } else {
+            self.ds_group
+                .new_dataset::<H5Msg>()
+                .chunk(CHUNK_SIZE)
+                .deflate(5)
+                .shape(0..)
+                .create(str_id.as_str())?
+        };
+
+        dataset
+            .new_attr::<u32>()
+            .create("hex_id")?
+            .write_scalar(&can_id.hex_id)?;
+
+        let desc = match &can_id.description {
+            Some(desc) => desc.as_str(),
+            None => "None",
+        };
+        create_str_attr(&dataset, "description", desc)?;
+
+        let unit = match &can_id.unit {
+            Some(unit) => unit.as_str(),
+            None => "None",
+        };
+        create_str_attr(&dataset, "unit", unit)?;
+
+        let scale = match &can_id.scale {
+            Some(scale) => scale,
+            None => &1.0,
+        };
+        dataset
+            .new_attr::<f32>()
+            .create("scale")?
+            .write_scalar(scale)?;
+        create_device_attrs(&dataset, can_id)?;
+
+        log::debug!("Created dataset {}", str_id);
+        Ok(dataset)
+    }
+
+    /// Writes the comments to "COMMENTS" and the information about the conversion to the root attributes.
+    pub fn finish(self, can_cmts: &[CanCmt], meta: &CanMeta) -> hdf5::Result<()> {
+        let root = &self.root;
+        create_str_attr(
+            root,
+            "CAN IDs file",
+            meta.can_ids_path.as_os_str().to_str().unwrap(),
+        )?;
+        root.new_attr::<usize>()
+            .create("Processing time")?
+            .write_scalar(&(meta.time_ms as usize))?;
+        root.new_attr::<usize>()
+            .create("Dataset count")?
+            .write_scalar(&self.datasets.len())?;
+        root.new_attr::<u64>()
+            .create("Previous size [B]")?
+            .write_scalar(&meta.old_size_b)?;
+        root.new_attr::<u32>()
+            .create("TS least trailing zeros")?
+            .write_scalar(&meta.least_trailing_zeros)?;
+        match meta.reference_date {
+            Some(date) => {
+                create_str_attr(root, "Reference date", &date.to_string())?;
+                create_str_attr(root, "Time zone", &meta.time_zone.to_string())?;
+                create_str_attr(root, "Timestamps", "ns since Unix epoch (UTC)")?;
+            }
+            None => {
+                create_str_attr(root, "Reference date", "None")?;
+                create_str_attr(root, "Timestamps", "ns since midnight of the first day")?;
+            }
+        }
+
+        if !can_cmts.is_empty() {
+            root.new_dataset_builder()
+                .with_data(can_cmts)
+                .set_filters(&[hdf5::filters::Filter::Deflate(5)])
+                .create("COMMENTS")?;
+
+            create_str_attr(
+                root,
+                "Comments file path",
+                meta.comments_path.unwrap().as_os_str().to_str().unwrap(),
+            )?;
+        }
+
+        for (i, logfile) in meta.log_paths.iter().enumerate() {
+            create_str_attr(
+                root,
+                &format!("Log file {i}"),
+                logfile.as_os_str().to_str().unwrap(),
+            )?;
+            let format = match meta.log_formats.get(i).copied().flatten() {
+                Some(format) => format.to_string(),
+                None => "unknown".to_string(),
+            };
+            create_str_attr(root, &format!("Log file {i} format"), &format)?;
+        }
+
+        log::debug!("Wrote comments to COMMENTS");
+
+        Ok(())
+    }
+}
+
+impl CanWriter for Hdf5Writer {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        Ok(Hdf5Writer::write_batch(self, can_msgs, can_ids)?)
+    }
+
+    fn dataset_count(&self) -> usize {
+        Hdf5Writer::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(self.root.filename())]
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        Ok(Hdf5Writer::finish(*self, can_cmts, meta)?)
+    }
+}
+
+/// Writes every collection as a dataset into the "CAN_IDs" group and the comments to "COMMENTS".
+pub fn write_to_hdf5<P: AsRef<Path>>(
+    output_path: &P,
+    collections: &[CanMsgCollection],
+    can_cmts: &[CanCmt],
+    meta: &CanMeta,
+) -> hdf5::Result<()> {
+    let mut writer = Hdf5Writer::create(output_path)?;
+    for collection in collections {
+        writer.append(&collection.can_id, &collection.collection)?;
+    }
+    writer.finish(can_cmts, meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::{ByteOrder, CanPayload};
+
+    fn msg(hex_id: u32, ts: u64, value: f32, data: Option<[u8; 2]>) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ts,
+            value,
+            payload: data.map(|[first, second]| CanPayload {
+                dlc: 2,
+                data: [first, second, 0, 0, 0, 0, 0, 0],
+            }),
+        }
+    }
+
+    fn str_attr(location: &Location, name: &str) -> String {
+        let value: VarLenUnicode = location.attr(name).unwrap().read_scalar().unwrap();
+        value.as_str().to_string()
+    }
+
+    fn output_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("can-parser-{}-{name}.h5", std::process::id()))
+    }
+
+    #[test]
+    fn appends_batches_to_chunked_datasets() {
+        let path = output_path("batches");
+        let flow = CanId {
+            hex_id: 0x100,
+            str_id: Some("CAN_ID_FLOW".to_string()),
+            unit: Some("L/min".to_string()),
+            scale: Some(0.5),
+            signals: vec![CanSignal {
+                name: "Flow".to_string(),
+                length: 16,
+                byte_order: ByteOrder::LittleEndian,
+                factor: 0.1,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let can_ids = HashMap::from([(0x100, flow)]);
+        let mut writer = Hdf5Writer::create(&path).unwrap().with_raw_payloads(true);
+        writer
+            .write_batch(
+                &[
+                    msg(0x100, 1_000, 1.0, Some([10, 0])),
+                    msg(0x200, 1_100, 5.0, None),
+                ],
+                &can_ids,
+            )
+            .unwrap();
+        writer
+            .write_batch(
+                &[
+                    msg(0x100, 2_000, 2.0, Some([20, 0])),
+                    msg(0x100, 3_000, 3.0, None),
+                ],
+                &can_ids,
+            )
+            .unwrap();
+        assert_eq!(writer.dataset_count(), 2);
+        writer.finish(&[], &CanMeta::for_tests()).unwrap();
+
+        let file = hdf5::File::open(&path).unwrap();
+        let flow = file.dataset("CAN_IDs/CAN_ID_FLOW").unwrap();
+        assert_eq!(flow.shape(), [3]);
+        let ts: Vec<u64> = flow
+            .read_raw::<H5Msg>()
+            .unwrap()
+            .iter()
+            .map(|m| m.ts)
+            .collect();
+        assert_eq!(ts, [1_000, 2_000, 3_000]);
+        assert_eq!(str_attr(&flow, "unit"), "L/min");
+        assert_eq!(
+            flow.attr("scale").unwrap().read_scalar::<f32>().unwrap(),
+            0.5
+        );
+        assert_eq!(
+            flow.attr("hex_id").unwrap().read_scalar::<u32>().unwrap(),
+            0x100
+        );
+
+        let unknown = file.dataset("CAN_IDs/512").unwrap();
+        assert_eq!(unknown.shape(), [1]);
+        assert_eq!(str_attr(&unknown, "unit"), "None");
+
+        let raw = file.dataset("CAN_RAW/CAN_ID_FLOW").unwrap();
+        let raw_data: Vec<[u8; 8]> = raw
+            .read_raw::<H5RawMsg>()
+            .unwrap()
+            .iter()
+            .map(|m| m.data)
+            .collect();
+        assert_eq!(raw_data.len(), 2);
+        assert_eq!(raw_data[1][0], 20);
+        assert!(file.dataset("CAN_RAW/512").is_err());
+
+        let signal = file.dataset("CAN_SIGNALS/CAN_ID_FLOW/Flow").unwrap();
+        let values: Vec<f64> = signal
+            .read_raw::<H5SignalValue>()
+            .unwrap()
+            .iter()
+            .map(|v| v.value)
+            .collect();
+        assert_eq!(values, [1.0, 2.0]);
+        assert_eq!(str_attr(&signal, "unit"), "None");
+        assert_eq!(
+            signal.attr("hex_id").unwrap().read_scalar::<u32>().unwrap(),
+            0x100
+        );
+        let dataset_count: usize = file.attr("Dataset count").unwrap().read_scalar().unwrap();
+        assert_eq!(dataset_count, 2);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn links_datasets_grouped_by_device() {
+        let path = output_path("devices");
+        let pump = CanId {
+            hex_id: 0x3100,
+            str_id: Some("CAN_ID_PUMP-DEV3".to_string()),
+            device: Some(3),
+            derived_from: Some("CAN_ID_PUMP".to_string()),
+            ..Default::default()
+        };
+        let can_ids = HashMap::from([(0x3100, pump)]);
+        let mut writer = Hdf5Writer::create(&path).unwrap().with_device_groups(true);
+        writer
+            .write_batch(&[msg(0x3100, 1_000, 1.0, None)], &can_ids)
+            .unwrap();
+        writer
+            .write_batch(&[msg(0x3100, 2_000, 2.0, None)], &can_ids)
+            .unwrap();
+        writer.finish(&[], &CanMeta::for_tests()).unwrap();
+
+        let file = hdf5::File::open(&path).unwrap();
+        let grouped = file.dataset("devices/DEV3/CAN_ID/CAN_ID_PUMP").unwrap();
+        assert_eq!(grouped.shape(), [2]);
+        assert_eq!(
+            grouped
+                .attr("device")
+                .unwrap()
+                .read_scalar::<u32>()
+                .unwrap(),
+            3
+        );
+        let linked = file.dataset("CAN_IDs/CAN_ID_PUMP-DEV3").unwrap();
+        assert_eq!(linked.shape(), [2]);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{dataset_name, remember_ids, CanMeta, CanWriter, WriterError};
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+/// Position of the header block, right after the identification block.
+const HD_POSITION: u64 = 64;
+/// A record is the time in ns since the start time as i64, followed by the raw value as f32.
+const RECORD_SIZE: u32 = 12;
+
+// Field values of the blocks, as far as they are used here (see ASAM MDF 4.1)
+const CN_TYPE_MASTER: u8 = 2;
+const SYNC_TIME: u8 = 1;
+const CN_DATA_SIGNED_LE: u8 = 2;
+const CN_DATA_FLOAT_LE: u8 = 4;
+const CC_TYPE_LINEAR: u8 = 1;
+const EV_TYPE_MARKER: u8 = 6;
+const EV_CAUSE_USER: u8 = 4;
+
+/// Identification block at the start of the file, marked unfinalized until the file is finished.
+fn id_block(finalized: bool) -> Vec<u8> {
+    let mut block = Vec::with_capacity(HD_POSITION as usize);
+    block.extend_from_slice(if finalized { b"MDF     " } else { b"UnFinMF " });
+    block.extend_from_slice(b"4.10    ");
+    block.extend_from_slice(b"CanPrsr ");
+    block.extend_from_slice(&[0; 4]);
+    block.extend_from_slice(&410u16.to_le_bytes());
+    block.resize(HD_POSITION as usize, 0);
+    block
+}
+
+/// Block with the header "##<id>", its links and data, padded to the 8 byte alignment of blocks.
+fn block(id: &[u8; 2], links: &[u64], data: &[u8]) -> Vec<u8> {
+    let length = 24 + 8 * links.len() + data.len();
+    let mut block = Vec::with_capacity(length.next_multiple_of(8));
+    block.extend_from_slice(b"##");
+    block.extend_from_slice(id);
+    block.extend_from_slice(&[0; 4]);
+    block.extend_from_slice(&(length as u64).to_le_bytes());
+    block.extend_from_slice(&(links.len() as u64).to_le_bytes());
+    for link in links {
+        block.extend_from_slice(&link.to_le_bytes());
+    }
+    block.extend_from_slice(data);
+    block.resize(length.next_multiple_of(8), 0);
+    block
+}
+
+/// Header block, linking to everything else in the file.
+fn hd_block(
+    dg_first: u64,
+    fh_first: u64,
+    ev_first: u64,
+    md_comment: u64,
+    start_ns: u64,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(32);
+    data.extend_from_slice(&start_ns.to_le_bytes());
+    // Time zone and daylight saving offsets, time flags (UTC), time class, flags and reserved
+    data.extend_from_slice(&[0; 8]);
+    // Start angle and distance
+    data.extend_from_slice(&[0; 16]);
+    block(
+        b"HD",
+        &[dg_first, fh_first, 0, 0, ev_first, md_comment],
+        &data,
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The data blocks of a CAN ID's channel group.
+#[derive(Default)]
+struct ChannelGroup {
+    /// Positions of the DT blocks
+    data_blocks: Vec<u64>,
+    /// Records before each data block
+    block_offsets: Vec<u64>,
+    cycle_count: u64,
+}
+
+/// Writes CAN messages to an ASAM MDF 4.1 file while they are parsed, e.g. for MDF viewers.
+///
+/// Every CAN ID gets its own data group with a single channel group, named by its str_id. It holds a
+/// master channel "time" in seconds and a value channel, named by the str_id as well, with the ID's unit
+/// and description. Values are stored divided by the ID's scale, with a linear conversion back.
+///
+/// The times are stored relative to the start time of the file, which is the first timestamp written,
+/// taken as UTC. Without a known recording date, it is on the 1st of January 1970. Comments become
+/// marker events, and the information about the conversion is in the file's comment.
+pub struct Mdf4Writer {
+    output_path: PathBuf,
+    file: BufWriter<File>,
+    /// Where the next block is appended
+    position: u64,
+    /// In ns, set by the first batch written
+    start_ts: Option<u64>,
+    groups: BTreeMap<u32, ChannelGroup>,
+    /// CAN IDs written so far
+    written_ids: BTreeMap<u32, CanId>,
+}
+
+impl Mdf4Writer {
+    /// Creates the file, which is marked unfinalized until [`Mdf4Writer::finish`] completes it.
+    pub fn create<P: AsRef<Path>>(output_path: &P) -> Result<Self, WriterError> {
+        let file = File::create(output_path).map_err(|e| WriterError::io(output_path, e))?;
+        let mut writer = Self {
+            output_path: output_path.as_ref().to_path_buf(),
+            file: BufWriter::new(file),
+            position: 0,
+            start_ts: None,
+            groups: BTreeMap::new(),
+            written_ids: BTreeMap::new(),
+        };
+        writer.write(&id_block(false))?;
+        writer.write(&hd_block(0, 0, 0, 0, 0))?;
+        Ok(writer)
+    }
+
+    /// Number of CAN IDs written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.written_ids.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<u64, WriterError> {
+        let position = self.position;
+        self.file
+            .write_all(bytes)
+            .map_err(|e| WriterError::io(&self.output_path, e))?;
+        self.position += bytes.len() as u64;
+        Ok(position)
+    }
+
+    /// Appends a block, returning its position.
+    fn append(&mut self, id: &[u8; 2], links: &[u64], data: &[u8]) -> Result<u64, WriterError> {
+        self.write(&block(id, links, data))
+    }
+
+    /// Appends a zero terminated text (TX) or XML (MD) block.
+    fn append_text(&mut self, id: &[u8; 2], text: &str) -> Result<u64, WriterError> {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        self.append(id, &[], &data)
+    }
+
+    fn append_optional_text(&mut self, text: Option<&str>) -> Result<u64, WriterError> {
+        match text {
+            Some(text) => self.append_text(b"TX", text),
+            None => Ok(0),
+        }
+    }
+
+    /// Appends a conversion block with physical value = `factor` * raw value + `offset`.
+    fn append_linear_conversion(&mut self, factor: f64, offset: f64) -> Result<u64, WriterError> {
+        let mut data = Vec::with_capacity(40);
+        data.push(CC_TYPE_LINEAR);
+        // Precision, flags, reference count
+        data.extend_from_slice(&[0; 5]);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        // Physical range
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&factor.to_le_bytes());
+        self.append(b"CC", &[0, 0, 0, 0], &data)
+    }
+
+    /// Appends a fixed length channel of `bit_count` bits at `byte_offset` in the record.
+    #[allow(clippy::too_many_arguments)]
+    fn append_channel(
+        &mut self,
+        next: u64,
+        name: u64,
+        conversion: u64,
+        unit: u64,
+        comment: u64,
+        (cn_type, sync_type, data_type): (u8, u8, u8),
+        byte_offset: u32,
+        bit_count: u32,
+    ) -> Result<u64, WriterError> {
+        let mut data = Vec::with_capacity(72);
+        data.extend_from_slice(&[cn_type, sync_type, data_type, 0]);
+        data.extend_from_slice(&byte_offset.to_le_bytes());
+        data.extend_from_slice(&bit_count.to_le_bytes());
+        // Flags, invalidation bit, precision, reserved, attachment count, value ranges and limits
+        data.resize(72, 0);
+        self.append(
+            b"CN",
+            &[next, 0, name, 0, conversion, 0, unit, comment],
+            &data,
+        )
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        remember_ids(&mut self.written_ids, can_msgs, can_ids);
+        let Some(first_ts) = can_msgs.iter().map(|msg| msg.ts).min() else {
+            return Ok(());
+        };
+        let start_ts = *self.start_ts.get_or_insert(first_ts) as i64;
+
+        let mut sorted = can_msgs.to_vec();
+        sorted.sort();
+        for msgs in sorted.chunk_by(|a, b| a.hex_id == b.hex_id) {
+            let hex_id = msgs[0].hex_id;
+            let scale = self.written_ids[&hex_id]
+                .scale
+                .filter(|&scale| scale != 0.0);
+            let mut records = Vec::with_capacity(msgs.len() * RECORD_SIZE as usize);
+            for msg in msgs {
+                let raw = match scale {
+                    Some(scale) => (msg.value as f64 / scale as f64) as f32,
+                    None => msg.value,
+                };
+                records.extend_from_slice(&(msg.ts as i64 - start_ts).to_le_bytes());
+                records.extend_from_slice(&raw.to_le_bytes());
+            }
+            let position = self.append(b"DT", &[], &records)?;
+            let group = self.groups.entry(hex_id).or_default();
+            group.data_blocks.push(position);
+            group.block_offsets.push(group.cycle_count);
+            group.cycle_count += msgs.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Appends the data group of a CAN ID, returning its position.
+    fn append_data_group(&mut self, hex_id: u32, next: u64) -> Result<u64, WriterError> {
+        let can_id = self.written_ids[&hex_id].clone();
+        let group = self.groups.remove(&hex_id).unwrap_or_default();
+
+        let data = match group.data_blocks[..] {
+            [] => 0,
+            [data_block] => data_block,
+            _ => {
+                let mut data = Vec::with_capacity(8 + 8 * group.data_blocks.len());
+                // Flags (offsets instead of an equal length) and reserved
+                data.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(&(group.data_blocks.len() as u32).to_le_bytes());
+                for offset in &group.block_offsets {
+                    data.extend_from_slice(&(offset * RECORD_SIZE as u64).to_le_bytes());
+                }
+                let links: Vec<u64> = std::iter::once(0).chain(group.data_blocks).collect();
+                self.append(b"DL", &links, &data)?
+            }
+        };
+
+        let name = self.append_text(b"TX", &dataset_name(&can_id))?;
+        let unit = self.append_optional_text(can_id.unit.as_deref())?;
+        let comment = self.append_optional_text(can_id.description.as_deref())?;
+        let conversion = match can_id.scale.filter(|&scale| scale != 0.0) {
+            Some(scale) => self.append_linear_conversion(scale as f64, 0.0)?,
+            None => 0,
+        };
+        let value_channel = self.append_channel(
+            0,
+            name,
+            conversion,
+            unit,
+            comment,
+            (0, 0, CN_DATA_FLOAT_LE),
+            8,
+            32,
+        )?;
+        let time_name = self.append_text(b"TX", "time")?;
+        let time_unit = self.append_text(b"TX", "s")?;
+        let time_conversion = self.append_linear_conversion(1e-9, 0.0)?;
+        let time_channel = self.append_channel(
+            value_channel,
+            time_name,
+            time_conversion,
+            time_unit,
+            0,
+            (CN_TYPE_MASTER, SYNC_TIME, CN_DATA_SIGNED_LE),
+            0,
+            64,
+        )?;
+
+        let mut cg_data = Vec::with_capacity(32);
+        // Record ID
+        cg_data.extend_from_slice(&[0; 8]);
+        cg_data.extend_from_slice(&group.cycle_count.to_le_bytes());
+        // Flags, path separator and reserved
+        cg_data.extend_from_slice(&[0; 8]);
+        cg_data.extend_from_slice(&RECORD_SIZE.to_le_bytes());
+        // Invalidation bytes
+        cg_data.extend_from_slice(&[0; 4]);
+        let channel_group = self.append(b"CG", &[0, time_channel, name, 0, 0, 0], &cg_data)?;
+
+        // Record ID size and reserved
+        self.append(b"DG", &[next, channel_group, data, 0], &[0; 8])
+    }
+
+    /// Appends a marker event for a comment, returning its position.
+    fn append_event(&mut self, cmt: &CanCmt, next: u64) -> Result<u64, WriterError> {
+        let name = self.append_text(b"TX", cmt.value.as_str())?;
+        let comment = self.append_text(b"TX", &format!("Comment {}", cmt.id))?;
+        let mut data = Vec::with_capacity(32);
+        data.extend_from_slice(&[EV_TYPE_MARKER, SYNC_TIME, 0, EV_CAUSE_USER]);
+        // Flags, reserved, scope count, attachment count and creator index
+        data.extend_from_slice(&[0; 12]);
+        let start_ts = self.start_ts.unwrap_or_default() as i64;
+        data.extend_from_slice(&(cmt.ts as i64 - start_ts).to_le_bytes());
+        data.extend_from_slice(&1e-9f64.to_le_bytes());
+        self.append(b"EV", &[next, 0, 0, name, comment], &data)
+    }
+
+    /// Writes the channel groups of all CAN IDs, the comments as events and the information about the
+    /// conversion, and marks the file as finalized.
+    pub fn finish(mut self, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        if self.start_ts.is_none() {
+            self.start_ts = can_cmts.iter().map(|cmt| cmt.ts).min();
+        }
+
+        let mut dg_first = 0;
+        let hex_ids: Vec<u32> = self.written_ids.keys().rev().copied().collect();
+        for hex_id in hex_ids {
+            dg_first = self.append_data_group(hex_id, dg_first)?;
+        }
+        let mut ev_first = 0;
+        for cmt in can_cmts.iter().rev() {
+            ev_first = self.append_event(cmt, ev_first)?;
+        }
+
+        let properties: String = meta
+            .entries(self.dataset_count())
+            .iter()
+            .map(|(key, value)| {
+                format!("<e name=\"{}\">{}</e>", escape_xml(key), escape_xml(value))
+            })
+            .collect();
+        let hd_comment = self.append_text(
+            b"MD",
+            &format!(
+                "<HDcomment><TX>CAN messages</TX><common_properties>{properties}</common_properties></HDcomment>"
+            ),
+        )?;
+        let fh_comment = self.append_text(
+            b"MD",
+            &format!(
+                "<FHcomment><TX>Created</TX><tool_id>{}</tool_id><tool_vendor></tool_vendor><tool_version>{}</tool_version></FHcomment>",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+        )?;
+        let now_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        let mut fh_data = now_ns.to_le_bytes().to_vec();
+        // Time zone and daylight saving offsets, time flags (UTC) and reserved
+        fh_data.extend_from_slice(&[0; 8]);
+        let fh_first = self.append(b"FH", &[0, fh_comment], &fh_data)?;
+
+        let hd = hd_block(
+            dg_first,
+            fh_first,
+            ev_first,
+            hd_comment,
+            self.start_ts.unwrap_or_default(),
+        );
+        let output_path = self.output_path.clone();
+        let io_error = |e| WriterError::io(&output_path, e);
+        self.file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        self.file.write_all(&id_block(true)).map_err(io_error)?;
+        self.file.write_all(&hd).map_err(io_error)?;
+        self.file.flush().map_err(io_error)?;
+        log::debug!(
+            "Wrote {} channel groups and {} events",
+            self.dataset_count(),
+            can_cmts.len()
+        );
+        Ok(())
+    }
+}
+
+impl CanWriter for Mdf4Writer {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        Mdf4Writer::write_batch(self, can_msgs, can_ids)
+    }
+
+    fn dataset_count(&self) -> usize {
+        Mdf4Writer::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        vec![self.output_path.clone()]
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        Mdf4Writer::finish(*self, can_cmts, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block read back from a file.
+    struct Block {
+        id: [u8; 2],
+        length: usize,
+        links: Vec<u64>,
+        data: Vec<u8>,
+    }
+
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    fn f64_at(bytes: &[u8], at: usize) -> f64 {
+        f64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    fn read_block(file: &[u8], position: u64) -> Block {
+        let start = position as usize;
+        assert_eq!(start % 8, 0, "block at {start} is not 8 byte aligned");
+        assert_eq!(&file[start..start + 2], b"##");
+        let length = u64_at(file, start + 8) as usize;
+        let link_count = u64_at(file, start + 16) as usize;
+        let links = (0..link_count)
+            .map(|i| u64_at(file, start + 24 + 8 * i))
+            .collect();
+        Block {
+            id: [file[start + 2], file[start + 3]],
+            length,
+            links,
+            data: file[start + 24 + 8 * link_count..start + length].to_vec(),
+        }
+    }
+
+    fn read_text(file: &[u8], position: u64) -> String {
+        let block = read_block(file, position);
+        assert!(&block.id == b"TX" || &block.id == b"MD");
+        let end = block.data.iter().position(|&b| b == 0).unwrap();
+        String::from_utf8(block.data[..end].to_vec()).unwrap()
+    }
+
+    /// Factor of the linear conversion at `position`.
+    fn read_factor(file: &[u8], position: u64) -> f64 {
+        let block = read_block(file, position);
+        assert_eq!(&block.id, b"CC");
+        assert_eq!(block.data[0], CC_TYPE_LINEAR);
+        f64_at(&block.data, 32)
+    }
+
+    fn msg(hex_id: u32, ts: u64, value: f32) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ts,
+            value,
+            payload: None,
+        }
+    }
+
+    #[test]
+    fn pads_blocks_to_eight_bytes() {
+        let tx = block(b"TX", &[1, 2], b"abc");
+        assert_eq!(tx.len(), 48);
+        assert_eq!(&tx[..4], b"##TX");
+        assert_eq!(u64_at(&tx, 8), 24 + 16 + 3);
+        assert_eq!(u64_at(&tx, 16), 2);
+        assert_eq!((u64_at(&tx, 24), u64_at(&tx, 32)), (1, 2));
+        assert_eq!(&tx[40..], b"abc\0\0\0\0\0");
+
+        let hd = hd_block(1, 2, 3, 4, 5);
+        assert_eq!(hd.len(), 104);
+        assert_eq!(u64_at(&hd, 8), 104);
+        assert_eq!(u64_at(&hd, 72), 5);
+    }
+
+    #[test]
+    fn marks_the_identification_block_finalized() {
+        let unfinished = id_block(false);
+        let finished = id_block(true);
+        assert_eq!(unfinished.len(), HD_POSITION as usize);
+        assert_eq!(&unfinished[..8], b"UnFinMF ");
+        assert_eq!(&finished[..16], b"MDF     4.10    ");
+        assert_eq!(u16::from_le_bytes([finished[28], finished[29]]), 410);
+    }
+
+    #[test]
+    fn links_groups_channels_data_and_events() {
+        let path = std::env::temp_dir().join(format!("can-parser-{}.mf4", std::process::id()));
+        let pressure = CanId {
+            hex_id: 1,
+            str_id: Some("PRESSURE".to_string()),
+            unit: Some("mmHg".to_string()),
+            scale: Some(0.5),
+            ..Default::default()
+        };
+        let can_ids = HashMap::from([(1, pressure)]);
+        let mut writer = Mdf4Writer::create(&path).unwrap();
+        writer
+            .write_batch(&[msg(1, 1_000, 1.0), msg(2, 1_500, 7.0)], &can_ids)
+            .unwrap();
+        writer
+            .write_batch(&[msg(1, 2_000, 2.0), msg(1, 3_000, 3.0)], &can_ids)
+            .unwrap();
+        let cmt = CanCmt {
+            id: 1,
+            ts: 2_500,
+            value: "Start".parse().unwrap(),
+        };
+        writer.finish(&[cmt], &CanMeta::for_tests()).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&file[..8], b"MDF     ");
+        let hd = read_block(&file, HD_POSITION);
+        assert_eq!(&hd.id, b"HD");
+        assert_eq!(u64_at(&hd.data, 0), 1_000);
+        assert!(read_text(&file, hd.links[5]).contains("<common_properties>"));
+        assert_eq!(&read_block(&file, hd.links[1]).id, b"FH");
+
+        // Data groups in the order of the IDs
+        let dg = read_block(&file, hd.links[0]);
+        assert_eq!(&dg.id, b"DG");
+        let cg = read_block(&file, dg.links[1]);
+        assert_eq!(&cg.id, b"CG");
+        assert_eq!(read_text(&file, cg.links[2]), "PRESSURE");
+        assert_eq!(u64_at(&cg.data, 8), 3);
+        assert_eq!(
+            u32::from_le_bytes(cg.data[24..28].try_into().unwrap()),
+            RECORD_SIZE
+        );
+
+        let time = read_block(&file, cg.links[1]);
+        assert_eq!(&time.id, b"CN");
+        assert_eq!(
+            &time.data[..3],
+            &[CN_TYPE_MASTER, SYNC_TIME, CN_DATA_SIGNED_LE]
+        );
+        assert_eq!(read_text(&file, time.links[2]), "time");
+        assert_eq!(read_factor(&file, time.links[4]), 1e-9);
+        let value = read_block(&file, time.links[0]);
+        assert_eq!(value.data[2], CN_DATA_FLOAT_LE);
+        assert_eq!(u32::from_le_bytes(value.data[4..8].try_into().unwrap()), 8);
+        assert_eq!(read_text(&file, value.links[6]), "mmHg");
+        assert_eq!(read_factor(&file, value.links[4]), 0.5);
+        assert_eq!(value.links[0], 0);
+
+        // Two batches make a data list with the byte offsets of its data blocks
+        let dl = read_block(&file, dg.links[2]);
+        assert_eq!(&dl.id, b"DL");
+        assert_eq!(u32::from_le_bytes(dl.data[4..8].try_into().unwrap()), 2);
+        assert_eq!((u64_at(&dl.data, 8), u64_at(&dl.data, 16)), (0, 12));
+        let first = read_block(&file, dl.links[1]);
+        assert_eq!(&first.id, b"DT");
+        assert_eq!(first.length, 24 + 12);
+        assert_eq!(u64_at(&first.data, 0), 0);
+        assert_eq!(
+            f32::from_le_bytes(first.data[8..12].try_into().unwrap()),
+            2.0
+        );
+        let second = read_block(&file, dl.links[2]);
+        assert_eq!(second.length, 24 + 2 * 12);
+        assert_eq!(u64_at(&second.data, 12), 2_000);
+
+        // An ID without information, with a single data block
+        let next = read_block(&file, dg.links[0]);
+        assert_eq!(next.links[0], 0);
+        let cg = read_block(&file, next.links[1]);
+        assert_eq!(read_text(&file, cg.links[2]), "2");
+        assert_eq!(u64_at(&cg.data, 8), 1);
+        let dt = read_block(&file, next.links[2]);
+        assert_eq!(&dt.id, b"DT");
+        assert_eq!(u64_at(&dt.data, 0), 500);
+        let value = read_block(&file, read_block(&file, cg.links[1]).links[0]);
+        assert_eq!(value.links[4], 0);
+
+        let ev = read_block(&file, hd.links[4]);
+        assert_eq!(&ev.id, b"EV");
+        assert_eq!(ev.links[0], 0);
+        assert_eq!(read_text(&file, ev.links[3]), "Start");
+        assert_eq!(
+            &ev.data[..4],
+            &[EV_TYPE_MARKER, SYNC_TIME, 0, EV_CAUSE_USER]
+        );
+        assert_eq!(u64_at(&ev.data, 16), 1_500);
+        assert_eq!(f64_at(&ev.data, 24), 1e-9);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::NaiveDate;
+
+use crate::parsers::{CanCmt, CanId, CanMsg, LogFormat, RecordingTimeZone};
+
+/// Path standing for the standard output.
+pub const STDOUT_PATH: &str = "-";
+
+mod arrow_ipc_writer;
+mod arrow_tables;
+mod csv_writer;
+mod hdf5_writer;
+mod mdf4_writer;
+mod parquet_writer;
+mod sqlite_writer;
+mod writer_error;
+pub use arrow_ipc_writer::ArrowIpcWriter;
+pub use csv_writer::{Alignment, CsvWriter};
+pub use hdf5_writer::{write_to_hdf5, Hdf5Writer};
+pub use mdf4_writer::Mdf4Writer;
+pub use parquet_writer::ParquetWriter;
+pub use sqlite_writer::SqliteWriter;
+pub use writer_error::WriterError;
+
+/// Information about a conversion run, stored alongside the converted data.
+pub struct CanMeta<'a> {
+    pub can_ids_path: &'a Path,
+    pub comments_path: Option<&'a Path>,
+    pub log_paths: &'a [PathBuf],
+    /// Format of every log file, `None` if it held nothing to detect the format from.
+    pub log_formats: &'a [Option<LogFormat>],
+    pub time_ms: u128,
+    pub old_size_b: u64,
+    pub least_trailing_zeros: u32,
+    /// Date the timestamps are relative to; without one, they count from midnight of the first day.
+    pub reference_date: Option<NaiveDate>,
+    pub time_zone: RecordingTimeZone,
+}
+
+impl CanMeta<'_> {
+    /// The information as key-value pairs, named like the root attributes of the HDF5 output.
+    pub fn entries(&self, dataset_count: usize) -> Vec<(String, String)> {
+        let mut entries = vec![
+            (
+                "CAN IDs file".to_string(),
+                self.can_ids_path.display().to_string(),
+            ),
+            ("Processing time".to_string(), self.time_ms.to_string()),
+            ("Dataset count".to_string(), dataset_count.to_string()),
+            ("Previous size [B]".to_string(), self.old_size_b.to_string()),
+            (
+                "TS least trailing zeros".to_string(),
+                self.least_trailing_zeros.to_string(),
+            ),
+        ];
+        match self.reference_date {
+            Some(date) => {
+                entries.push(("Reference date".to_string(), date.to_string()));
+                entries.push(("Time zone".to_string(), self.time_zone.to_string()));
+                entries.push((
+                    "Timestamps".to_string(),
+                    "ns since Unix epoch (UTC)".to_string(),
+                ));
+            }
+            None => {
+                entries.push(("Reference date".to_string(), "None".to_string()));
+                entries.push((
+                    "Timestamps".to_string(),
+                    "ns since midnight of the first day".to_string(),
+                ));
+            }
+        }
+        if let Some(comments_path) = self.comments_path {
+            entries.push((
+                "Comments file path".to_string(),
+                comments_path.display().to_string(),
+            ));
+        }
+        for (i, log_path) in self.log_paths.iter().enumerate() {
+            entries.push((format!("Log file {i}"), log_path.display().to_string()));
+            let format = match self.log_formats.get(i).copied().flatten() {
+                Some(format) => format.to_string(),
+                None => "unknown".to_string(),
+            };
+            entries.push((format!("Log file {i} format"), format));
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+impl CanMeta<'static> {
+    /// Information about a conversion of "ids.h" without logs, for the tests of the writers.
+    pub(crate) fn for_tests() -> Self {
+        CanMeta {
+            can_ids_path: Path::new("ids.h"),
+            comments_path: None,
+            log_paths: &[],
+            log_formats: &[],
+            time_ms: 0,
+            old_size_b: 0,
+            least_trailing_zeros: 0,
+            reference_date: None,
+            time_zone: RecordingTimeZone::Utc,
+        }
+    }
+}
+
+/// Name of the dataset (or table, or file) of a CAN ID: its name, or its number if it has none.
+pub(crate) fn dataset_name(can_id: &CanId) -> String {
+    match &can_id.str_id {
+        Some(str_id) => str_id.to_owned(),
+        None => can_id.hex_id.to_string(),
+    }
+}
+
+/// Metadata of a CAN ID, named like the attributes of its HDF5 dataset.
+pub(crate) fn id_metadata(can_id: &CanId) -> Vec<(&'static str, String)> {
+    vec![
+        ("hex_id", can_id.hex_id.to_string()),
+        (
+            "description",
+            can_id.description.as_deref().unwrap_or("None").to_string(),
+        ),
+        ("unit", can_id.unit.as_deref().unwrap_or("None").to_string()),
+        ("scale", can_id.scale.unwrap_or(1.0).to_string()),
+    ]
+}
+
+/// Remembers the CAN IDs of messages not seen before. IDs missing in `can_ids` are remembered without
+/// any information besides their numerical value.
+pub(crate) fn remember_ids(
+    written_ids: &mut BTreeMap<u32, CanId>,
+    can_msgs: &[CanMsg],
+    can_ids: &HashMap<u32, CanId>,
+) {
+    for msg in can_msgs {
+        written_ids
+            .entry(msg.hex_id)
+            .or_insert_with(|| match can_ids.get(&msg.hex_id) {
+                Some(can_id) => can_id.clone(),
+                None => CanId::empty_with_id(msg.hex_id),
+            });
+    }
+}
+
+/// Path of the comments table next to the output file ("<stem>.comments.<extension>"), or in the
+/// output directory ("COMMENTS.<extension>").
+pub(crate) fn comments_path(output_path: &Path, in_dir: bool, extension: &str) -> PathBuf {
+    if in_dir {
+        return output_path.join(format!("COMMENTS.{extension}"));
+    }
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    output_path.with_file_name(format!("{stem}.comments.{extension}"))
+}
+
+/// Whether the output is written to the standard output instead of a file.
+pub fn is_stdout<P: AsRef<Path>>(path: &P) -> bool {
+    path.as_ref() == Path::new(STDOUT_PATH)
+}
+
+/// A sink for converted CAN messages, which are written batch by batch while they are parsed.
+pub trait CanWriter {
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError>;
+
+    /// Number of CAN IDs written so far.
+    fn dataset_count(&self) -> usize;
+
+    /// Files and directories created so far, in the order they were created, which are removed again
+    /// when the conversion is aborted. Directories which existed before are not included.
+    fn created_paths(&self) -> Vec<PathBuf>;
+
+    /// Writes the comments and the information about the conversion, completing the output.
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError>;
+}
+
+/// The formats the converted messages can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Hdf5,
+    Parquet,
+    ArrowIpc,
+    Sqlite,
+    Csv,
+    Mdf4,
+}
+
+impl OutputFormat {
+    /// Guesses the format from the extension of the output path, HDF5 unless it is known.
+    /// The standard output defaults to Arrow IPC.
+    pub fn from_path<P: AsRef<Path>>(path: &P) -> OutputFormat {
+        if is_stdout(path) {
+            return OutputFormat::ArrowIpc;
+        }
+        let ext = match path.as_ref().extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => return OutputFormat::Hdf5,
+        };
+        match ext.as_str() {
+            "parquet" => OutputFormat::Parquet,
+            "arrow" | "arrows" | "feather" | "ipc" => OutputFormat::ArrowIpc,
+            "sqlite" | "sqlite3" | "db" => OutputFormat::Sqlite,
+            "csv" => OutputFormat::Csv,
+            "mf4" | "mdf" => OutputFormat::Mdf4,
+            _ => OutputFormat::Hdf5,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Hdf5 => write!(f, "HDF5"),
+            OutputFormat::Parquet => write!(f, "Parquet"),
+            OutputFormat::ArrowIpc => write!(f, "Arrow IPC"),
+            OutputFormat::Sqlite => write!(f, "SQLite"),
+            OutputFormat::Csv => write!(f, "CSV"),
+            OutputFormat::Mdf4 => write!(f, "MDF4"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    /// Accepts "hdf5", "parquet", "arrow", "sqlite", "csv" or "mdf4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hdf5" | "h5" => Ok(OutputFormat::Hdf5),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(OutputFormat::ArrowIpc),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            "csv" => Ok(OutputFormat::Csv),
+            "mdf4" | "mdf" | "mf4" => Ok(OutputFormat::Mdf4),
+            _ => Err(format!(
+                "Invalid output format {s:?}, expected hdf5, parquet, arrow, sqlite, csv or mdf4"
+            )),
+        }
+    }
+}
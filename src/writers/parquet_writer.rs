@@ -0,0 +1,366 @@
+use arrow::datatypes::SchemaRef;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::arrow_tables::{
+    comments_batch, comments_schema, id_batch, id_schema, long_batch, long_schema,
+};
+use super::{
+    comments_path, dataset_name, id_metadata, remember_ids, CanMeta, CanWriter, WriterError,
+};
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+fn create_table(
+    path: &Path,
+    schema: SchemaRef,
+    metadata: Vec<KeyValue>,
+) -> Result<ArrowWriter<File>, WriterError> {
+    let file = File::create(path).map_err(|e| WriterError::io(&path, e))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_key_value_metadata(Some(metadata))
+        .build();
+    Ok(ArrowWriter::try_new(file, schema, Some(props))?)
+}
+
+/// Writes CAN messages to Apache Parquet while they are parsed.
+///
+/// By default, all messages go into a single long table with the columns hex_id (u32), str_id (String),
+/// ts (u64) and value (f32). The unit, scale and description of every CAN ID are stored in the file's
+/// key-value metadata as "<str_id>.unit" and so on.
+///
+/// With [`ParquetWriter::with_file_per_id`], the output path is a directory holding a file per CAN ID
+/// with the columns ts and value, and the CAN ID's metadata as "unit" and so on.
+///
+/// Comments are written to a sibling table with the columns id (u32), ts (u64) and comment (String),
+/// "<output>.comments.parquet" or "COMMENTS.parquet" in the output directory. The information about
+/// the conversion is added to the key-value metadata of every file.
+pub struct ParquetWriter {
+    output_path: PathBuf,
+    file_per_id: bool,
+    /// Long table, created when the first batch is written
+    table: Option<ArrowWriter<File>>,
+    /// By CAN ID, when writing a file per ID
+    id_tables: HashMap<u32, ArrowWriter<File>>,
+    /// CAN IDs written so far
+    written_ids: BTreeMap<u32, CanId>,
+    /// Files and directories created so far
+    created_paths: Vec<PathBuf>,
+}
+
+impl ParquetWriter {
+    /// Files are only created once messages (or comments) are written.
+    pub fn new<P: AsRef<Path>>(output_path: &P) -> Self {
+        Self {
+            output_path: output_path.as_ref().to_path_buf(),
+            file_per_id: false,
+            table: None,
+            id_tables: HashMap::new(),
+            written_ids: BTreeMap::new(),
+            created_paths: vec![],
+        }
+    }
+
+    /// Write a file per CAN ID into the output directory instead of a single long table.
+    pub fn with_file_per_id(mut self, file_per_id: bool) -> Self {
+        self.file_per_id = file_per_id;
+        self
+    }
+
+    /// Number of CAN IDs written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.written_ids.len()
+    }
+
+    /// Path of the table holding the comments.
+    pub fn comments_path(&self) -> PathBuf {
+        comments_path(&self.output_path, self.file_per_id, "parquet")
+    }
+
+    /// Files and directories created so far, the output directory only if it did not exist before.
+    pub fn created_paths(&self) -> Vec<PathBuf> {
+        self.created_paths.clone()
+    }
+
+    fn create_output_dir(&mut self) -> Result<(), WriterError> {
+        if self.output_path.is_dir() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.output_path)
+            .map_err(|e| WriterError::io(&self.output_path, e))?;
+        self.created_paths.push(self.output_path.clone());
+        Ok(())
+    }
+
+    fn long_table(&mut self) -> Result<&mut ArrowWriter<File>, WriterError> {
+        if self.table.is_none() {
+            let schema = long_schema(HashMap::new());
+            self.table = Some(create_table(&self.output_path, schema, vec![])?);
+            self.created_paths.push(self.output_path.clone());
+        }
+        Ok(self.table.as_mut().unwrap())
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        remember_ids(&mut self.written_ids, can_msgs, can_ids);
+        if self.file_per_id {
+            let mut sorted = can_msgs.to_vec();
+            sorted.sort();
+            for msgs in sorted.chunk_by(|a, b| a.hex_id == b.hex_id) {
+                self.append(msgs)?;
+            }
+            return Ok(());
+        }
+
+        let batch = long_batch(&long_schema(HashMap::new()), can_msgs, &self.written_ids)?;
+        self.long_table()?.write(&batch)?;
+        Ok(())
+    }
+
+    /// Appends messages which all belong to the same, already known, CAN ID to its own file.
+    fn append(&mut self, can_msgs: &[CanMsg]) -> Result<(), WriterError> {
+        let hex_id = can_msgs[0].hex_id;
+        if !self.id_tables.contains_key(&hex_id) {
+            self.create_output_dir()?;
+            let can_id = &self.written_ids[&hex_id];
+            let path = self.output_path.join(format!(
+                "{}.parquet",
+                dataset_name(can_id).replace('/', "_")
+            ));
+            let metadata = id_metadata(can_id)
+                .into_iter()
+                .map(|(key, value)| KeyValue::new(key.to_string(), value))
+                .collect();
+            self.id_tables
+                .insert(hex_id, create_table(&path, id_schema(can_id), metadata)?);
+            log::debug!("Created {:?}", path.as_os_str());
+            self.created_paths.push(path);
+        }
+        let batch = id_batch(&id_schema(&self.written_ids[&hex_id]), can_msgs)?;
+        self.id_tables.get_mut(&hex_id).unwrap().write(&batch)?;
+        Ok(())
+    }
+
+    /// Writes the comments to their table and the information about the conversion (and, for the
+    /// long table, about the CAN IDs) to the key-value metadata, closing all files.
+    pub fn finish(mut self, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        let entries: Vec<KeyValue> = meta
+            .entries(self.dataset_count())
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value))
+            .collect();
+
+        if self.file_per_id {
+            self.create_output_dir()?;
+            for (_, mut table) in self.id_tables.drain() {
+                for entry in &entries {
+                    table.append_key_value_metadata(entry.clone());
+                }
+                table.close()?;
+            }
+        } else {
+            let id_entries: Vec<KeyValue> = self
+                .written_ids
+                .values()
+                .flat_map(|can_id| {
+                    let name = dataset_name(can_id);
+                    id_metadata(can_id)
+                        .into_iter()
+                        .map(move |(key, value)| KeyValue::new(format!("{name}.{key}"), value))
+                })
+                .collect();
+            let table = self.long_table()?;
+            for entry in id_entries.into_iter().chain(entries.iter().cloned()) {
+                table.append_key_value_metadata(entry);
+            }
+            self.table.take().unwrap().close()?;
+        }
+
+        if !can_cmts.is_empty() {
+            let comments_path = self.comments_path();
+            let mut table = create_table(&comments_path, comments_schema(), entries)?;
+            table.write(&comments_batch(can_cmts)?)?;
+            table.close()?;
+            log::debug!("Wrote comments to {:?}", comments_path.as_os_str());
+        }
+        Ok(())
+    }
+}
+
+impl CanWriter for ParquetWriter {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        ParquetWriter::write_batch(self, can_msgs, can_ids)
+    }
+
+    fn dataset_count(&self) -> usize {
+        ParquetWriter::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        ParquetWriter::created_paths(self)
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        ParquetWriter::finish(*self, can_cmts, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Float32Array, UInt64Array};
+    use arrow::datatypes::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn msg(hex_id: u32, ts: u64, value: f32) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ts,
+            value,
+            payload: None,
+        }
+    }
+
+    fn can_ids() -> HashMap<u32, CanId> {
+        let pressure = CanId {
+            hex_id: 1,
+            str_id: Some("PRESSURE".to_string()),
+            description: Some("Blood pressure".to_string()),
+            unit: Some("mmHg".to_string()),
+            scale: Some(0.5),
+            ..Default::default()
+        };
+        HashMap::from([(1, pressure)])
+    }
+
+    fn cmt() -> CanCmt {
+        CanCmt {
+            id: 1,
+            ts: 1_200,
+            value: "Start".parse().unwrap(),
+        }
+    }
+
+    fn reader(path: &Path) -> ParquetRecordBatchReaderBuilder<File> {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap()
+    }
+
+    fn key_values(reader: &ParquetRecordBatchReaderBuilder<File>) -> HashMap<String, String> {
+        let key_values = reader.metadata().file_metadata().key_value_metadata();
+        key_values
+            .into_iter()
+            .flatten()
+            .map(|kv| (kv.key.clone(), kv.value.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("can-parser-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_a_long_table_with_the_metadata_of_the_ids() {
+        let dir = output_dir("parquet-long");
+        let path = dir.join("out.parquet");
+        let mut writer = ParquetWriter::new(&path);
+        writer
+            .write_batch(&[msg(1, 1_000, 1.5), msg(2, 1_500, 7.0)], &can_ids())
+            .unwrap();
+        writer
+            .write_batch(&[msg(1, 2_000, 2.5)], &can_ids())
+            .unwrap();
+        assert_eq!(writer.created_paths(), vec![path.clone()]);
+        writer.finish(&[cmt()], &CanMeta::for_tests()).unwrap();
+
+        let table = reader(&path);
+        let fields: Vec<(&str, &DataType)> = table
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("hex_id", &DataType::UInt32),
+                ("str_id", &DataType::Utf8),
+                ("ts", &DataType::UInt64),
+                ("value", &DataType::Float32)
+            ]
+        );
+        let metadata = key_values(&table);
+        assert_eq!(metadata["PRESSURE.unit"], "mmHg");
+        assert_eq!(metadata["PRESSURE.scale"], "0.5");
+        assert_eq!(metadata["PRESSURE.description"], "Blood pressure");
+        assert_eq!(metadata["2.unit"], "None");
+        assert_eq!(metadata["Dataset count"], "2");
+        let rows: usize = table.build().unwrap().map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        let comments = reader(&dir.join("out.comments.parquet"));
+        assert_eq!(comments.metadata().file_metadata().num_rows(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_a_file_per_id() {
+        let dir = output_dir("parquet-per-id");
+        let path = dir.join("out");
+        let mut writer = ParquetWriter::new(&path).with_file_per_id(true);
+        writer
+            .write_batch(&[msg(1, 1_000, 1.5), msg(2, 1_500, 7.0)], &can_ids())
+            .unwrap();
+        writer
+            .write_batch(&[msg(1, 2_000, 2.5)], &can_ids())
+            .unwrap();
+        assert_eq!(
+            writer.created_paths(),
+            [
+                path.clone(),
+                path.join("PRESSURE.parquet"),
+                path.join("2.parquet")
+            ]
+        );
+        writer.finish(&[cmt()], &CanMeta::for_tests()).unwrap();
+
+        let table = reader(&path.join("PRESSURE.parquet"));
+        let value = table.schema().field_with_name("value").unwrap().clone();
+        assert_eq!(value.metadata()["unit"], "mmHg");
+        assert_eq!(value.metadata()["scale"], "0.5");
+        assert_eq!(key_values(&table)["Dataset count"], "2");
+        let batch = table.build().unwrap().next().unwrap().unwrap();
+        let ts = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        let values = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(ts.values(), &[1_000, 2_000]);
+        assert_eq!(values.values(), &[1.5, 2.5]);
+
+        assert!(path.join("2.parquet").exists());
+        assert!(path.join("COMMENTS.parquet").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::{remember_ids, CanMeta, CanWriter, WriterError};
+use crate::parsers::{CanCmt, CanId, CanMsg};
+
+const SCHEMA: &str = "
+CREATE TABLE can_ids (
+    hex_id INTEGER PRIMARY KEY,
+    str_id TEXT,
+    description TEXT,
+    unit TEXT,
+    scale REAL,
+    device INTEGER,
+    device_name TEXT,
+    derived_from TEXT,
+    has_messages INTEGER NOT NULL
+);
+CREATE TABLE messages (
+    hex_id INTEGER NOT NULL,
+    ts INTEGER NOT NULL,
+    value REAL NOT NULL
+);
+CREATE TABLE comments (
+    id INTEGER NOT NULL,
+    ts INTEGER NOT NULL,
+    comment TEXT NOT NULL
+);
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// Created after all messages are inserted, which is faster than updating them on every insert.
+const INDICES: &str = "
+CREATE INDEX messages_hex_id_ts ON messages (hex_id, ts);
+CREATE INDEX messages_ts ON messages (ts);
+CREATE INDEX comments_ts ON comments (ts);
+";
+
+/// Writes CAN messages to an SQLite database while they are parsed.
+///
+/// The database has the tables can_ids (hex_id, str_id, description, unit, scale, device, device_name,
+/// derived_from, has_messages) with every CAN ID known or written, messages (hex_id, ts, value) indexed by ID and time,
+/// comments (id, ts, comment) and meta (key, value) with the information about the conversion, named
+/// like the root attributes of the HDF5 output. Timestamps are stored as signed 64 bit integers.
+pub struct SqliteWriter {
+    conn: Connection,
+    /// CAN IDs written so far
+    written_ids: BTreeMap<u32, CanId>,
+    /// CAN IDs passed along with the batches, with or without messages
+    known_ids: BTreeMap<u32, CanId>,
+}
+
+impl SqliteWriter {
+    /// Creates the database, replacing an existing file.
+    pub fn create<P: AsRef<Path>>(output_path: &P) -> Result<Self, WriterError> {
+        let output_path = output_path.as_ref();
+        if output_path.exists() {
+            std::fs::remove_file(output_path).map_err(|e| WriterError::io(&output_path, e))?;
+        }
+        let conn = Connection::open(output_path)?;
+        // A failed conversion is discarded anyway, so there is no need for a journal.
+        conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn,
+            written_ids: BTreeMap::new(),
+            known_ids: BTreeMap::new(),
+        })
+    }
+
+    /// Number of CAN IDs written so far.
+    pub fn dataset_count(&self) -> usize {
+        self.written_ids.len()
+    }
+
+    /// Appends a batch of messages of arbitrary CAN IDs. IDs missing in `can_ids` are written without
+    /// any information besides their numerical value.
+    pub fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        remember_ids(&mut self.written_ids, can_msgs, can_ids);
+        for (hex_id, can_id) in can_ids {
+            self.known_ids.insert(*hex_id, can_id.clone());
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached("INSERT INTO messages VALUES (?1, ?2, ?3)")?;
+            for msg in can_msgs {
+                insert.execute(params![msg.hex_id, msg.ts as i64, msg.value])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Writes all CAN IDs, marking those with messages, the comments and the information about the conversion, and indexes the tables.
+    pub fn finish(mut self, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        let entries = meta.entries(self.dataset_count());
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO can_ids VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?;
+            let mut hex_ids: Vec<&u32> = self.known_ids.keys().collect();
+            hex_ids.extend(self.written_ids.keys());
+            hex_ids.sort();
+            hex_ids.dedup();
+            for hex_id in hex_ids {
+                let can_id = self
+                    .known_ids
+                    .get(hex_id)
+                    .unwrap_or_else(|| &self.written_ids[hex_id]);
+                insert.execute(params![
+                    can_id.hex_id,
+                    can_id.str_id,
+                    can_id.description,
+                    can_id.unit,
+                    can_id.scale,
+                    can_id.device,
+                    can_id.device_name,
+                    can_id.derived_from,
+                    self.written_ids.contains_key(hex_id),
+                ])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO comments VALUES (?1, ?2, ?3)")?;
+            for cmt in can_cmts {
+                insert.execute(params![cmt.id, cmt.ts as i64, cmt.value.as_str()])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO meta VALUES (?1, ?2)")?;
+            for (key, value) in &entries {
+                insert.execute(params![key, value])?;
+            }
+        }
+        tx.commit()?;
+        self.conn.execute_batch(INDICES)?;
+        log::debug!("Wrote {} comments and indexed the messages", can_cmts.len());
+        Ok(())
+    }
+}
+
+impl CanWriter for SqliteWriter {
+    fn write_batch(
+        &mut self,
+        can_msgs: &[CanMsg],
+        can_ids: &HashMap<u32, CanId>,
+    ) -> Result<(), WriterError> {
+        SqliteWriter::write_batch(self, can_msgs, can_ids)
+    }
+
+    fn dataset_count(&self) -> usize {
+        SqliteWriter::dataset_count(self)
+    }
+
+    fn created_paths(&self) -> Vec<PathBuf> {
+        self.conn.path().map(PathBuf::from).into_iter().collect()
+    }
+
+    fn finish(self: Box<Self>, can_cmts: &[CanCmt], meta: &CanMeta) -> Result<(), WriterError> {
+        SqliteWriter::finish(*self, can_cmts, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(hex_id: u32, ts: u64, value: f32) -> CanMsg {
+        CanMsg {
+            hex_id,
+            ts,
+            value,
+            payload: None,
+        }
+    }
+
+    fn rows(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn writes_all_tables_and_indices() {
+        let path = std::env::temp_dir().join(format!("can-parser-{}.sqlite", std::process::id()));
+        let pressure = CanId {
+            hex_id: 1,
+            str_id: Some("PRESSURE".to_string()),
+            unit: Some("mmHg".to_string()),
+            scale: Some(0.5),
+            ..Default::default()
+        };
+        let unused = CanId {
+            hex_id: 3,
+            str_id: Some("UNUSED".to_string()),
+            ..Default::default()
+        };
+        let can_ids = HashMap::from([(1, pressure), (3, unused)]);
+        let mut writer = SqliteWriter::create(&path).unwrap();
+        writer
+            .write_batch(&[msg(1, 1_000, 1.5), msg(2, 1_500, 7.0)], &can_ids)
+            .unwrap();
+        writer.write_batch(&[msg(1, 2_000, 2.5)], &can_ids).unwrap();
+        let cmt = CanCmt {
+            id: 1,
+            ts: 1_200,
+            value: "Start".parse().unwrap(),
+        };
+        writer.finish(&[cmt], &CanMeta::for_tests()).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(
+            rows(
+                &conn,
+                "SELECT hex_id || ' ' || ifnull(str_id, '-') || ' ' || ifnull(unit, '-') || ' ' \
+                 || ifnull(scale, '-') || ' ' || has_messages FROM can_ids ORDER BY hex_id"
+            ),
+            ["1 PRESSURE mmHg 0.5 1", "2 - - - 1", "3 UNUSED - - 0"]
+        );
+        assert_eq!(
+            rows(
+                &conn,
+                "SELECT hex_id || ' ' || ts || ' ' || value FROM messages ORDER BY ts"
+            ),
+            ["1 1000 1.5", "2 1500 7.0", "1 2000 2.5"]
+        );
+        assert_eq!(
+            rows(
+                &conn,
+                "SELECT id || ' ' || ts || ' ' || comment FROM comments"
+            ),
+            ["1 1200 Start"]
+        );
+        assert_eq!(
+            rows(&conn, "SELECT value FROM meta WHERE key = 'Dataset count'"),
+            ["2"]
+        );
+        assert_eq!(
+            rows(
+                &conn,
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL ORDER BY name"
+            ),
+            ["comments_ts", "messages_hex_id_ts", "messages_ts"]
+        );
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}